@@ -0,0 +1,295 @@
+//! Estimating the slowly varying background under a spectrum, e.g. the
+//! chemical noise that slopes up towards low m/z in MALDI spectra.
+
+use crate::{ MSPoint, calcs };
+
+use std::collections::VecDeque;
+
+// the polynomial fit of a segment stops once less than this fraction of the
+// intensity is clipped in an iteration
+const FIT_TOLERANCE: f64 = 1e-4;
+const MAX_FIT_ITERATIONS: usize = 100;
+
+/// The algorithms a baseline can be estimated with
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Method {
+  Snip,
+  TopHat,
+  Polynomial,
+}
+
+impl Method {
+  pub const ALL: [Method; 3] = [
+    Method::Snip,
+    Method::TopHat,
+    Method::Polynomial,
+  ];
+}
+
+impl std::fmt::Display for Method {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        Method::Snip       => "SNIP",
+        Method::TopHat     => "Top-Hat",
+        Method::Polynomial => "Piecewise Polynomial",
+      }
+    )
+  }
+}
+
+/// How to estimate a baseline. width is counted in points: the largest
+/// clipping window of SNIP, the half-width of the structuring element of the
+/// top-hat filter, or the length of the segments polynomials are fitted to.
+/// degree is only used by the polynomial fit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Baseline {
+  pub method: Method,
+  pub width:  usize,
+  pub degree: usize,
+}
+
+impl Default for Baseline {
+  fn default() -> Self {
+    Baseline {
+      method: Method::Snip,
+      width:  50,
+      degree: 2,
+    }
+  }
+}
+
+impl Baseline {
+
+  /// The baseline under each of the points. It is zero where there is too
+  /// little to estimate it from, so that subtracting it leaves the points.
+  pub fn estimate(&self, pts: &[MSPoint]) -> Vec<f64> {
+    if pts.len() < 3 || self.width == 0 {
+      return vec![0.0; pts.len()];
+    }
+    
+    let ints: Vec<f64> = pts.iter().map(|pt| { pt.int }).collect();
+    
+    match self.method {
+      Method::Snip   => snip(&ints, self.width),
+      Method::TopHat => top_hat(&ints, self.width),
+      Method::Polynomial => {
+        let mzs: Vec<f64> = pts.iter().map(|pt| { pt.mz }).collect();
+        polynomial(&mzs, &ints, self.width, self.degree)
+      }
+    }
+  }
+
+  /// How the correction is listed in the processing history
+  pub fn describe(&self) -> String {
+    match self.method {
+      Method::Polynomial => format!(
+        "Baseline correction ({}, {} points, degree {})", self.method, self.width, self.degree
+      ),
+      _ => format!("Baseline correction ({}, {} points)", self.method, self.width),
+    }
+  }
+}
+
+// Statistics-sensitive Non-linear Iterative Peak-clipping. Each point is
+// replaced by the mean of its neighbours p points away if that is lower, for
+// growing p. Done on a log-log-square root scale, which keeps the clipping
+// from eating into the bases of large peaks.
+fn snip(ints: &[f64], width: usize) -> Vec<f64> {
+  let n = ints.len();
+  
+  let mut v: Vec<f64> = ints.iter()
+    .map(|y| { ((y.max(0.0) + 1.0).sqrt() + 1.0).ln().ln_1p() })
+    .collect();
+  let mut clipped = v.clone();
+  
+  for p in 1..=width.min((n - 1) / 2) {
+    for i in p..n-p {
+      clipped[i] = v[i].min((v[i-p] + v[i+p]) / 2.0);
+    }
+    v.copy_from_slice(&clipped);
+  }
+  
+  v.iter()
+    .map(|v| { ((v.exp() - 1.0).exp() - 1.0).powi(2) - 1.0 })
+    .collect()
+}
+
+// The morphological opening (an erosion followed by a dilation) follows the
+// spectrum everywhere except for features narrower than the structuring
+// element, i.e. the peaks. Subtracting it is the top-hat transform.
+fn top_hat(ints: &[f64], half_width: usize) -> Vec<f64> {
+  let eroded = running_extreme(ints, half_width, |new, old| { new <= old });
+  running_extreme(&eroded, half_width, |new, old| { new >= old })
+}
+
+// The minimum or maximum over a window of 2 * half + 1 points around each
+// point, in linear time. beats(new, old) tells whether new replaces old as
+// the extreme.
+fn running_extreme(ys: &[f64], half: usize, beats: fn(f64, f64) -> bool) -> Vec<f64> {
+  let n = ys.len();
+  let mut out = Vec::with_capacity(n);
+  // indices of the candidates, their values in order of beats
+  let mut window: VecDeque<usize> = VecDeque::new();
+  
+  for j in 0..n + half {
+    if j < n {
+      while let Some(&last) = window.back() {
+        if beats(ys[j], ys[last]) {
+          window.pop_back();
+        } else {
+          break;
+        }
+      }
+      window.push_back(j);
+    }
+    
+    if j >= half {
+      let i = j - half;
+      while window[0] + half < i {
+        window.pop_front();
+      }
+      out.push(ys[window[0]]);
+    }
+  }
+  
+  out
+}
+
+// Fits polynomials to overlapping segments of the spectrum and blends them,
+// each weighted most in the middle of its segment
+fn polynomial(mzs: &[f64], ints: &[f64], width: usize, degree: usize) -> Vec<f64> {
+  let n    = ints.len();
+  let len  = width.max(degree + 2).min(n);
+  let step = (len / 2).max(1);
+  
+  let mut sum     = vec![0.0; n];
+  let mut weights = vec![0.0; n];
+  
+  let mut start = 0;
+  loop {
+    // a tail too short for the next segment to fit goes into this one
+    let end = if n.saturating_sub(start + step) <= degree { n } else { (start + len).min(n) };
+    let fit = fit_segment(&mzs[start..end], &ints[start..end], degree);
+    
+    for (k, b) in fit.iter().enumerate() {
+      let w = usize::min(k + 1, end - start - k) as f64;
+      sum[start + k]     += w * b;
+      weights[start + k] += w;
+    }
+    
+    if end == n {
+      break;
+    }
+    start += step;
+  }
+  
+  sum.iter().zip(weights.iter())
+    .map(|(s, w)| { s / w })
+    .collect()
+}
+
+// Modified polyfit: points above the fitted curve are pulled down onto it and
+// the fit is repeated, until only the background is left below the curve
+fn fit_segment(mzs: &[f64], ints: &[f64], degree: usize) -> Vec<f64> {
+  
+  // m/z scaled to -1..1 keeps the normal equations well conditioned
+  let (lo, hi) = (mzs[0], mzs[mzs.len() - 1]);
+  let scale = if hi > lo { 2.0 / (hi - lo) } else { 1.0 };
+  let xs: Vec<f64> = mzs.iter().map(|mz| { (mz - lo) * scale - 1.0 }).collect();
+  
+  let total: f64 = ints.iter().map(|y| { y.abs() }).sum();
+  let mut ys  = ints.to_vec();
+  let mut fit = vec![];
+  
+  for _ in 0..MAX_FIT_ITERATIONS {
+    // a lower degree where the points do not determine the polynomial, the
+    // lowest point where they determine none at all
+    let c = match (0..=degree).rev().find_map(|d| { calcs::polyfit(&xs, &ys, d) }) {
+      Some(c) => c,
+      None    => vec![ys.iter().copied().fold(f64::INFINITY, f64::min)],
+    };
+    fit = xs.iter().map(|x| { calcs::polyval(&c, *x) }).collect();
+    
+    // clipped against the original points, so that points pulled down by a
+    // fit that was tilted by a peak come back up once the fit does
+    let mut change = 0.0;
+    for ((y, f), int) in ys.iter_mut().zip(fit.iter()).zip(ints.iter()) {
+      let clipped = f.min(*int);
+      change += (*y - clipped).abs();
+      *y = clipped;
+    }
+    
+    if change <= FIT_TOLERANCE * total {
+      break;
+    }
+  }
+  
+  fit
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn points( ints: &[f64] ) -> Vec<MSPoint> {
+    ints.iter().enumerate()
+      .map(|(i, int)| { MSPoint { mz: 100.0 + i as f64 * 0.1, int: *int, snr: 0.0 } })
+      .collect()
+  }
+
+  // a sloping background of 10 to 20 with a narrow peak on top at 50
+  fn sloped_peak() -> Vec<MSPoint> {
+    let ints: Vec<f64> = (0..101)
+      .map(|i| {
+        let x = i as f64;
+        10.0 + x / 10.0 + 500.0 * f64::exp(-(x - 50.0).powi(2) / 2.0)
+      })
+      .collect();
+    points(&ints)
+  }
+
+  fn baseline( method: Method, width: usize ) -> Baseline {
+    Baseline { method, width, degree: 1 }
+  }
+
+  #[test]
+  fn follows_background() {
+    let pts = sloped_peak();
+    // polynomials need segments much wider than the peak to pass under it
+    for (method, width) in [(Method::Snip, 10), (Method::TopHat, 10), (Method::Polynomial, 40)] {
+      let b = baseline(method, width).estimate(&pts);
+      assert_eq!(b.len(), pts.len());
+      // under the peak and away from it
+      for i in [20, 50, 80] {
+        let background = 10.0 + i as f64 / 10.0;
+        assert!((b[i] - background).abs() < 1.0, "{} gives {} at {}", method, b[i], i);
+      }
+      assert!(b.iter().zip(pts.iter()).all(|(b, pt)| { *b <= pt.int + 1e-6 }), "{} lies above", method);
+    }
+  }
+
+  #[test]
+  fn top_hat_keeps_wide_features() {
+    let ints = [0.0, 0.0, 5.0, 5.0, 5.0, 5.0, 5.0, 0.0, 9.0, 0.0, 0.0];
+    assert_eq!(top_hat(&ints, 1), vec![0.0, 0.0, 5.0, 5.0, 5.0, 5.0, 5.0, 0.0, 0.0, 0.0, 0.0]);
+  }
+
+  // a short last segment is fitted along with the one before
+  #[test]
+  fn fits_short_tail() {
+    let pts = points(&(0..23).map(|i| { 2.0 * i as f64 }).collect::<Vec<f64>>());
+    let b = Baseline { method: Method::Polynomial, width: 10, degree: 2 }.estimate(&pts);
+    for (b, pt) in b.iter().zip(pts.iter()) {
+      assert!((b - pt.int).abs() < 1e-6);
+    }
+  }
+
+  #[test]
+  fn zero_without_enough_points() {
+    assert_eq!(baseline(Method::Snip, 10).estimate(&points(&[5.0, 6.0])), vec![0.0, 0.0]);
+    assert_eq!(baseline(Method::TopHat, 0).estimate(&points(&[5.0, 6.0, 7.0])), vec![0.0; 3]);
+  }
+}
@@ -0,0 +1,449 @@
+//! The numerical building blocks the processing is made of.
+
+use crate::{ Centroiding, MSPoint, Noise, Peak };
+
+// windows with fewer points than this do not get a noise estimate of their own
+const MIN_NOISE_POINTS: usize = 5;
+
+/// Sorts floats in ascending order. NaNs cannot make it panic; they end up
+/// at either end.
+pub fn sort_in_place(arr: &mut [f64]) {
+  arr.sort_by(|a, b| {a.total_cmp(b)});
+}
+
+/// Indices of the local maxima of the intensity
+pub fn get_maxima(pts: &[MSPoint]) -> Vec<usize> {
+  let mut rising   = false;
+  let mut last_int = 0.0;
+  let mut maxima   = vec![];
+  
+  for (i, pt) in pts.iter().enumerate() {
+    if last_int > pt.int {
+      if rising {
+        maxima.push(i-1);
+      }
+      rising = false;
+    } else {
+      rising = true;
+    }
+    last_int = pt.int;
+  }
+  
+  maxima
+}
+
+/// Sets the S/N of the given maxima from the noise around each of them
+pub fn update_snrs(
+  pts:    &mut [MSPoint], 
+  maxima: &[usize],
+  noise:  &[Noise]
+) {
+  for i in maxima {
+    let (level, width) = noise_at(noise, pts[*i].mz);
+    if width > 0.0 {
+      pts[*i].snr = (pts[*i].int - level) / width;
+    }
+  }
+}
+
+/// The median intensity and twice the median absolute deviation from it.
+/// Reorders values.
+pub fn noise_of(values: &mut [f64]) -> (f64, f64) {
+  sort_in_place(values);
+  let level = values[ values.len() / 2 ];
+  
+  for v in values.iter_mut() {
+    *v = f64::abs(*v - level);
+  }
+  sort_in_place(values);
+  
+  (level, values[ values.len() / 2 ] * 2.0)
+}
+
+/// Estimates the noise in consecutive windows of the given m/z width. Each
+/// estimate belongs to the middle of its window.
+pub fn estimate_noise(pts: &[MSPoint], window: f64) -> Vec<Noise> {
+  let mut noise = vec![];
+  
+  if pts.is_empty() || window <= 0.0 {
+    return noise;
+  }
+  
+  let mut start = 0;
+  while start < pts.len() {
+    let from = pts[start].mz;
+    let end  = start + pts[start..].partition_point(|pt| { pt.mz < from + window });
+    
+    if end - start >= MIN_NOISE_POINTS {
+      let mut ints: Vec<f64> = pts[start..end].iter().map(|pt| { pt.int }).collect();
+      let (level, width) = noise_of(&mut ints);
+      noise.push(Noise {
+        mz: (from + pts[end - 1].mz) / 2.0,
+        level,
+        width,
+      });
+    }
+    
+    start = end.max(start + 1);
+  }
+  
+  noise
+}
+
+/// The noise level and width at mz, interpolated between the estimates
+pub fn noise_at(noise: &[Noise], mz: f64) -> (f64, f64) {
+  let i = noise.partition_point(|n| { n.mz < mz });
+  
+  if noise.is_empty() {
+    (0.0, 0.0)
+  } else if i == 0 {
+    (noise[0].level, noise[0].width)
+  } else if i == noise.len() {
+    (noise[i-1].level, noise[i-1].width)
+  } else {
+    let (a, b) = (&noise[i-1], &noise[i]);
+    let t = (mz - a.mz) / (b.mz - a.mz);
+    (a.level + t * (b.level - a.level), a.width + t * (b.width - a.width))
+  }
+}
+
+/// Adds up spectra that were not necessarily sampled at the same m/z values.
+/// Every spectrum is linearly interpolated onto the union of all m/z values.
+pub fn sum_spectra(spectra: &Vec<&Vec<MSPoint>>) -> Vec<MSPoint> {
+
+  let mut mzs: Vec<f64> = 
+    spectra.iter()
+      .flat_map(|pts| { pts.iter().map(|pt| { pt.mz }) })
+      .collect();
+  sort_in_place(&mut mzs);
+  mzs.dedup();
+  
+  let mut summed: Vec<MSPoint> = 
+    mzs.iter()
+      .map(|mz| { MSPoint { mz: *mz, int: 0.0, snr: 0.0 } })
+      .collect();
+  
+  for pts in spectra {
+    if pts.is_empty() { continue; }
+    
+    let mut j = 0;
+    for pt in summed.iter_mut() {
+      while j < pts.len() && pts[j].mz < pt.mz {
+        j += 1;
+      }
+      
+      if j == pts.len() {
+        break;
+      }
+      
+      if pts[j].mz == pt.mz {
+        pt.int += pts[j].int;
+      } else if j > 0 {
+        let (a, b) = (&pts[j-1], &pts[j]);
+        pt.int += a.int + (b.int - a.int) * (pt.mz - a.mz) / (b.mz - a.mz);
+      }
+    }
+  }
+  
+  summed
+}
+
+/// Least-squares fit of a polynomial of the given degree. Returns the
+/// coefficients, constant term first, or None if the points do not determine
+/// the polynomial.
+pub fn polyfit(xs: &[f64], ys: &[f64], degree: usize) -> Option<Vec<f64>> {
+  let n = degree + 1;
+  
+  if xs.len() < n {
+    return None;
+  }
+  
+  // normal equations: (X^T X) c = X^T y
+  let mut a = vec![vec![0.0; n + 1]; n];
+  for (x, y) in xs.iter().zip(ys.iter()) {
+    let mut powers = vec![1.0; 2 * n];
+    for k in 1..2*n {
+      powers[k] = powers[k-1] * x;
+    }
+    for row in 0..n {
+      for col in 0..n {
+        a[row][col] += powers[row + col];
+      }
+      a[row][n] += powers[row] * y;
+    }
+  }
+  
+  solve(a)
+}
+
+// Gaussian elimination with partial pivoting on an augmented matrix
+fn solve(mut a: Vec<Vec<f64>>) -> Option<Vec<f64>> {
+  let n = a.len();
+  
+  for col in 0..n {
+    let pivot = (col..n)
+      .max_by(|i, j| { a[*i][col].abs().partial_cmp(&a[*j][col].abs()).unwrap() })?;
+    if a[pivot][col].abs() < 1e-12 {
+      return None;
+    }
+    a.swap(col, pivot);
+    
+    let pivot_row = a[col].clone();
+    for row in a.iter_mut().skip(col+1) {
+      let factor = row[col] / pivot_row[col];
+      for (x, p) in row.iter_mut().zip(pivot_row.iter()).skip(col) {
+        *x -= factor * p;
+      }
+    }
+  }
+  
+  let mut c = vec![0.0; n];
+  for row in (0..n).rev() {
+    let sum: f64 = (row+1..n).map(|k| { a[row][k] * c[k] }).sum();
+    c[row] = (a[row][n] - sum) / a[row][row];
+  }
+  
+  Some(c)
+}
+
+/// Evaluates a polynomial given by its coefficients, constant term first
+pub fn polyval(c: &[f64], x: f64) -> f64 {
+  c.iter().rev().fold(0.0, |acc, ci| { acc * x + ci })
+}
+
+/// Fits a peak around the point with index i: its apex, FWHM and area.
+/// The peak reaches down to the nearest local minimum on either side.
+pub fn fit_peak(pts: &[MSPoint], i: usize, centroiding: Centroiding) -> Peak {
+  let apex = &pts[i];
+  let half = apex.int / 2.0;
+  
+  // where the peak ends, walking downhill
+  let mut left = i;
+  while left > 0 && pts[left - 1].int <= pts[left].int {
+    left -= 1;
+  }
+  let mut right = i;
+  while right + 1 < pts.len() && pts[right + 1].int <= pts[right].int {
+    right += 1;
+  }
+  
+  let left_half  = half_height_crossing(pts, i, left,  half);
+  let right_half = half_height_crossing(pts, i, right, half);
+  
+  // a peak that does not fall to half height on one side (e.g. when it
+  // overlaps a neighbour) is assumed to be symmetric
+  let fwhm = match (left_half, right_half) {
+    (Some(l), Some(r)) => r - l,
+    (Some(l), None)    => 2.0 * (apex.mz - l),
+    (None,    Some(r)) => 2.0 * (r - apex.mz),
+    (None,    None)    => 0.0,
+  };
+  
+  let area: f64 = pts[left..=right].windows(2)
+    .map(|w| { (w[1].mz - w[0].mz) * (w[0].int + w[1].int) / 2.0 })
+    .sum();
+  
+  let (mz, int) = match centroiding {
+    Centroiding::Parabolic => parabola_vertex(pts, i, |y| { y }, |y| { y }),
+    Centroiding::Gaussian  => parabola_vertex(pts, i, |y| { y.max(f64::MIN_POSITIVE).ln() }, |y| { y.exp() }),
+    Centroiding::HalfHeight => {
+      let above = pts[left..=right].iter().filter(|pt| { pt.int >= half });
+      let (sum, weighted) = above.fold((0.0, 0.0), |(s, w), pt| { (s + pt.int, w + pt.int * pt.mz) });
+      Some((if sum > 0.0 { weighted / sum } else { apex.mz }, apex.int))
+    }
+  }.unwrap_or((apex.mz, apex.int));
+  
+  Peak {
+    index: i,
+    mz,
+    int,
+    fwhm,
+    area,
+  }
+}
+
+// the m/z at which the peak falls to half height between the apex and end,
+// interpolated linearly
+fn half_height_crossing(pts: &[MSPoint], apex: usize, end: usize, half: f64) -> Option<f64> {
+  let mut j = apex;
+  
+  while j != end {
+    let next = if end < apex { j - 1 } else { j + 1 };
+    let (a, b) = (&pts[j], &pts[next]);
+    
+    if b.int <= half {
+      let t = if a.int > b.int { (a.int - half) / (a.int - b.int) } else { 0.0 };
+      return Some(a.mz + t * (b.mz - a.mz));
+    }
+    j = next;
+  }
+  
+  None
+}
+
+// The vertex of the parabola through the apex and its two neighbours, after
+// transforming the intensities with to; from turns the height back.
+// Some((mz, int)) unless the parabola opens upwards.
+fn parabola_vertex(
+  pts:  &[MSPoint],
+  i:    usize,
+  to:   fn(f64) -> f64,
+  from: fn(f64) -> f64
+) -> Option<(f64, f64)> {
+  if i == 0 || i + 1 >= pts.len() {
+    return None;
+  }
+  
+  let (x0, x1, x2) = (pts[i-1].mz, pts[i].mz, pts[i+1].mz);
+  let (y0, y1, y2) = (to(pts[i-1].int), to(pts[i].int), to(pts[i+1].int));
+  
+  // y = a (x - x1)^2 + b (x - x1) + y1
+  let (d0, d2) = (x0 - x1, x2 - x1);
+  let denom = d0 * d2 * (d0 - d2);
+  if denom == 0.0 {
+    return None;
+  }
+  let a = ((y0 - y1) * d2 - (y2 - y1) * d0) / denom;
+  let b = ((y2 - y1) * d0 * d0 - (y0 - y1) * d2 * d2) / denom;
+  
+  if a >= 0.0 {
+    return None;
+  }
+  
+  let dx = -b / (2.0 * a);
+  // the apex cannot be further out than the neighbours
+  if dx < d0 || dx > d2 {
+    return None;
+  }
+  
+  Some((x1 + dx, from(y1 + b * dx / 2.0)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sorts_nan() {
+    let mut values = vec![3.0, f64::NAN, 1.0, 2.0];
+    sort_in_place(&mut values);
+    assert_eq!(&values[..3], &[1.0, 2.0, 3.0]);
+    assert!(values[3].is_nan());
+  }
+
+  fn noise( mz: f64, level: f64, width: f64 ) -> Noise {
+    Noise { mz, level, width }
+  }
+
+  #[test]
+  fn median_noise() {
+    let mut values = vec![5.0, 4.0, 6.0, 100.0, 5.0];
+    assert_eq!(noise_of(&mut values), (5.0, 2.0));
+  }
+
+  // the noise of the quiet half does not take on that of the noisy one
+  #[test]
+  fn estimates_noise_per_window() {
+    let pts: Vec<MSPoint> = (0..40)
+      .map(|i| {
+        let scatter = if i < 20 { 1.0 } else { 10.0 };
+        let int = 50.0 + if i % 2 == 0 { scatter } else { -scatter };
+        MSPoint { mz: 100.0 + i as f64, int, snr: 0.0 }
+      })
+      .collect();
+
+    let estimates = estimate_noise(&pts, 10.0);
+    assert_eq!(estimates.len(), 4);
+    assert_eq!(estimates[0].mz, 104.5);
+    assert_eq!(estimates[1].width, estimates[0].width);
+    assert_eq!(estimates[3].width, 10.0 * estimates[0].width);
+    assert!(estimates.iter().all(|n| { (n.level - 50.0).abs() <= 10.0 }));
+  }
+
+  #[test]
+  fn skips_sparse_windows() {
+    let pts: Vec<MSPoint> = [100.0, 101.0, 102.0, 103.0, 104.0, 150.0, 151.0]
+      .iter()
+      .map(|mz| { MSPoint { mz: *mz, int: 1.0, snr: 0.0 } })
+      .collect();
+    let estimates = estimate_noise(&pts, 10.0);
+    assert_eq!(estimates.len(), 1);
+    assert_eq!(estimates[0].mz, 102.0);
+    assert!(estimate_noise(&pts, 0.0).is_empty());
+  }
+
+  #[test]
+  fn interpolates_noise() {
+    let estimates = [noise(100.0, 10.0, 2.0), noise(200.0, 20.0, 4.0)];
+    assert_eq!(noise_at(&estimates, 150.0), (15.0, 3.0));
+    assert_eq!(noise_at(&estimates, 50.0), (10.0, 2.0));
+    assert_eq!(noise_at(&estimates, 250.0), (20.0, 4.0));
+    assert_eq!(noise_at(&[], 150.0), (0.0, 0.0));
+  }
+
+  #[test]
+  fn snr_from_local_noise() {
+    let mut pts: Vec<MSPoint> = [(100.0, 30.0), (200.0, 30.0)]
+      .iter()
+      .map(|(mz, int)| { MSPoint { mz: *mz, int: *int, snr: 0.0 } })
+      .collect();
+    update_snrs(&mut pts, &[0, 1], &[noise(100.0, 10.0, 2.0), noise(200.0, 20.0, 5.0)]);
+    assert_eq!(pts[0].snr, 10.0);
+    assert_eq!(pts[1].snr, 2.0);
+  }
+
+  // a Gaussian of height 100 and sigma 0.05 at 500.013, sampled every 0.01
+  fn gaussian_peak() -> Vec<MSPoint> {
+    (0..61)
+      .map(|i| {
+        let mz = 499.7 + i as f64 * 0.01;
+        let int = 100.0 * f64::exp(-(mz - 500.013).powi(2) / (2.0 * 0.05 * 0.05));
+        MSPoint { mz, int, snr: 0.0 }
+      })
+      .collect()
+  }
+
+  #[test]
+  fn fits_gaussian_peak() {
+    let pts  = gaussian_peak();
+    let apex = get_maxima(&pts)[0];
+
+    // the logarithm of a Gaussian is a parabola
+    let peak = fit_peak(&pts, apex, Centroiding::Gaussian);
+    assert!((peak.mz - 500.013).abs() < 1e-9);
+    assert!((peak.int - 100.0).abs() < 1e-6);
+
+    for centroiding in Centroiding::ALL {
+      let peak = fit_peak(&pts, apex, centroiding);
+      assert_eq!(peak.index, apex);
+      assert!((peak.mz - 500.013).abs() < 0.002, "{} gives {}", centroiding, peak.mz);
+      assert!((peak.fwhm - 2.3548 * 0.05).abs() < 0.002, "fwhm {}", peak.fwhm);
+      assert!((peak.area - 100.0 * 0.05 * f64::sqrt(2.0 * std::f64::consts::PI)).abs() < 0.01);
+    }
+  }
+
+  // a peak running into a neighbour before half height is taken to be
+  // symmetric
+  #[test]
+  fn mirrors_half_width() {
+    let pts: Vec<MSPoint> = [(1.0, 20.0), (2.0, 60.0), (3.0, 100.0), (4.0, 80.0), (5.0, 70.0), (6.0, 90.0)]
+      .iter()
+      .map(|(mz, int)| { MSPoint { mz: *mz, int: *int, snr: 0.0 } })
+      .collect();
+    let peak = fit_peak(&pts, 2, Centroiding::Parabolic);
+    assert!((peak.fwhm - 2.0 * 1.25).abs() < 1e-9);
+    assert!((peak.area - (40.0 + 80.0 + 90.0 + 75.0)).abs() < 1e-9);
+  }
+
+  #[test]
+  fn apex_at_the_edge() {
+    let pts: Vec<MSPoint> = [(1.0, 100.0), (2.0, 40.0), (3.0, 10.0)]
+      .iter()
+      .map(|(mz, int)| { MSPoint { mz: *mz, int: *int, snr: 0.0 } })
+      .collect();
+    for centroiding in Centroiding::ALL {
+      let peak = fit_peak(&pts, 0, centroiding);
+      assert_eq!((peak.mz, peak.int), (1.0, 100.0), "{}", centroiding);
+    }
+  }
+}
@@ -0,0 +1,260 @@
+//! Peak detection by continuous wavelet transform. The spectrum is
+//! transformed with Mexican hat wavelets of growing width; a peak shows up as
+//! a ridge of maxima running across the scales, while noise spikes only do so
+//! at the smallest ones.
+
+use crate::{ MSPoint, calcs };
+
+// scales per doubling of the wavelet width
+const SCALES_PER_OCTAVE: f64 = 4.0;
+// how far a ridge has to reach up for a peak, in doublings of the width
+const MIN_OCTAVES: f64 = 2.0;
+// how many scales a ridge may go without a maximum before it ends
+const MAX_GAP: usize = 1;
+// the noise of a ridge is taken from this many points to either side
+const NOISE_POINTS: usize = 250;
+// the wavelet is cut off this many widths from its centre
+const SUPPORT: f64 = 5.0;
+
+/// Wavelets narrower than this many points are not sampled finely enough
+pub const MIN_SCALE: f64 = 0.5;
+
+/// The scale limits of the wavelets, as widths in points. A peak's ridge is
+/// strongest at a scale close to its FWHM in points, so the limits should
+/// enclose the widths of the peaks sought.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cwt {
+  pub min_scale: f64,
+  pub max_scale: f64,
+}
+
+impl Default for Cwt {
+  fn default() -> Self {
+    Cwt {
+      min_scale: 1.0,
+      max_scale: 32.0,
+    }
+  }
+}
+
+// a chain of maxima across the scales, followed from the widest down
+struct Ridge {
+  pos:        usize, // where it was last seen
+  length:     usize, // in scales
+  gap:        usize,
+  best:       f64,   // the largest coefficient on the ridge
+  best_scale: usize,
+}
+
+impl Cwt {
+
+  /// The scales the spectrum is transformed at, from the smallest up. None
+  /// is below MIN_SCALE.
+  pub fn scales(&self) -> Vec<f64> {
+    let min = self.min_scale.max(MIN_SCALE);
+    let max = self.max_scale.max(min);
+    let n   = (SCALES_PER_OCTAVE * (max / min).log2()).round() as usize + 1;
+
+    (0..n.max(2))
+      .map(|k| { min * (max / min).powf(k as f64 / (n.max(2) - 1) as f64) })
+      .collect()
+  }
+
+  /// Indices of the peaks found, each with the S/N of its ridge: the largest
+  /// coefficient on the ridge over the 95th percentile of the magnitudes of
+  /// the smallest scale's coefficients around it.
+  ///
+  /// A ridge counts as a peak if it spans two doublings of the wavelet width
+  /// (9 scales, or all of them if there are fewer), reaches down to the
+  /// smallest scale and is strongest above it.
+  /// Scales wider than the spectrum are cut down to its length. The points
+  /// are assumed to be about evenly spaced.
+  pub fn detect(&self, pts: &[MSPoint]) -> Vec<(usize, f64)> {
+
+    if pts.len() < 3 {
+      return vec![];
+    }
+
+    let ints: Vec<f64>        = pts.iter().map(|pt| { pt.int }).collect();
+    let len                   = pts.len() as f64;
+    let within                = Cwt { min_scale: self.min_scale.min(len), max_scale: self.max_scale.min(len) };
+    let scales                = within.scales();
+    let coeffs: Vec<Vec<f64>> = scales.iter().map(|a| { transform(&ints, *a) }).collect();
+
+    let mut active: Vec<Ridge> = vec![];
+
+    for (s, row) in coeffs.iter().enumerate().rev() {
+      let maxima = coefficient_maxima(row);
+      let mut taken = vec![false; maxima.len()];
+      let reach = scales[s].ceil() as usize;
+
+      // every ridge moves on to the nearest free maximum within reach
+      for ridge in active.iter_mut() {
+        let from = maxima.partition_point(|m| { m + reach < ridge.pos });
+        let next = (from..maxima.len())
+          .take_while(|k| { maxima[*k] <= ridge.pos + reach })
+          .filter(|k| { !taken[*k] })
+          .min_by_key(|k| { (maxima[*k] as isize - ridge.pos as isize).abs() });
+
+        match next {
+          Some(k) => {
+            taken[k] = true;
+            ridge.pos     = maxima[k];
+            ridge.length += 1;
+            ridge.gap     = 0;
+            if row[ridge.pos] > ridge.best {
+              ridge.best       = row[ridge.pos];
+              ridge.best_scale = s;
+            }
+          }
+          None => { ridge.gap += 1; }
+        }
+      }
+
+      // ridges without maxima for too long have ended, above the smallest
+      // scale, and are no peaks
+      active.retain(|ridge| { ridge.gap <= MAX_GAP });
+
+      // maxima no ridge went to start ridges of their own
+      for (k, m) in maxima.iter().enumerate() {
+        if !taken[k] {
+          active.push(Ridge {
+            pos:        *m,
+            length:     1,
+            gap:        0,
+            best:       row[*m],
+            best_scale: s,
+          });
+        }
+      }
+    }
+
+    let min_length = ((MIN_OCTAVES * SCALES_PER_OCTAVE) as usize + 1).min(scales.len());
+    let smallest   = &coeffs[0];
+    let snap       = scales[0].ceil() as usize;
+
+    let mut peaks: Vec<(usize, f64)> = active.iter()
+      .filter(|ridge| { ridge.length >= min_length && ridge.gap == 0 && ridge.best_scale > 0 })
+      .map(|ridge| {
+        let noise = noise_around(smallest, ridge.pos);
+        let snr   = if noise > 0.0 { ridge.best / noise } else { f64::INFINITY };
+        (highest_near(&ints, ridge.pos, snap), snr)
+      })
+      .collect();
+
+    peaks.sort_by_key(|(i, _)| { *i });
+    peaks.dedup_by_key(|(i, _)| { *i });
+    peaks
+  }
+}
+
+// (1 - t^2) e^(-t^2/2), the negative second derivative of a Gaussian
+fn mexican_hat(t: f64) -> f64 {
+  (1.0 - t * t) * (-t * t / 2.0).exp()
+}
+
+// The wavelet coefficients at scale a, normalised by 1/sqrt(a). The first
+// and last intensities are carried on beyond the ends, so the ends do not
+// look like edges of peaks.
+fn transform(ys: &[f64], a: f64) -> Vec<f64> {
+  let half    = (SUPPORT * a).ceil() as isize;
+  let norm    = 1.0 / a.sqrt();
+  let wavelet: Vec<f64> = (-half..=half).map(|k| { norm * mexican_hat(k as f64 / a) }).collect();
+  let n = ys.len() as isize;
+
+  (0..n)
+    .map(|i| {
+      (i - half..=i + half)
+        .map(|j| { wavelet[(j - i + half) as usize] * ys[j.clamp(0, n - 1) as usize] })
+        .sum()
+    })
+    .collect()
+}
+
+// indices of the positive local maxima of a row of coefficients
+fn coefficient_maxima(row: &[f64]) -> Vec<usize> {
+  (1..row.len().saturating_sub(1))
+    .filter(|i| { row[*i] > 0.0 && row[*i] >= row[i-1] && row[*i] > row[i+1] })
+    .collect()
+}
+
+// the 95th percentile of the magnitudes of the coefficients around i
+fn noise_around(row: &[f64], i: usize) -> f64 {
+  let from = i.saturating_sub(NOISE_POINTS);
+  let to   = (i + NOISE_POINTS + 1).min(row.len());
+
+  let mut magnitudes: Vec<f64> = row[from..to].iter().map(|c| { c.abs() }).collect();
+  calcs::sort_in_place(&mut magnitudes);
+  magnitudes[(magnitudes.len() - 1) * 95 / 100]
+}
+
+// The ridge of a peak ends next to its apex; the highest point within reach
+// of it is taken as the apex
+fn highest_near(ints: &[f64], i: usize, reach: usize) -> usize {
+  let from = i.saturating_sub(reach);
+  let to   = (i + reach).min(ints.len() - 1);
+
+  (from..=to).fold(i, |best, j| { if ints[j] > ints[best] { j } else { best } })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Gaussian peaks of the given (index, height, sigma in points) on a flat
+  // background with a little deterministic noise
+  fn spectrum( peaks: &[(usize, f64, f64)] ) -> Vec<MSPoint> {
+    let mut seed: u64 = 1;
+    (0..400)
+      .map(|i| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let noise = (seed >> 40) as f64 / (1u64 << 24) as f64;
+        let int = 10.0 + noise + peaks.iter()
+          .map(|(at, height, sigma)| { height * f64::exp(-(i as f64 - *at as f64).powi(2) / (2.0 * sigma * sigma)) })
+          .sum::<f64>();
+        MSPoint { mz: 500.0 + i as f64 * 0.01, int, snr: 0.0 }
+      })
+      .collect()
+  }
+
+  #[test]
+  fn scales_double_every_four_steps() {
+    let scales = Cwt::default().scales();
+    assert_eq!(scales.len(), 21);
+    assert_eq!(scales[0], 1.0);
+    assert!((scales[4] - 2.0).abs() < 1e-9);
+    assert!((scales[20] - 32.0).abs() < 1e-9);
+
+    let scales = Cwt { min_scale: 0.1, max_scale: 0.2 }.scales();
+    assert_eq!(scales, vec![MIN_SCALE, MIN_SCALE]);
+  }
+
+  #[test]
+  fn finds_peaks_of_different_widths() {
+    let pts = spectrum(&[(100, 200.0, 2.0), (250, 100.0, 6.0)]);
+    assert_eq!(found(&pts), vec![100, 250]);
+  }
+
+  // peaks above S/N 3, as the callers would threshold them
+  fn found( pts: &[MSPoint] ) -> Vec<usize> {
+    Cwt::default().detect(pts).iter()
+      .filter(|(_, snr)| { *snr >= 3.0 })
+      .map(|(i, _)| { *i })
+      .collect()
+  }
+
+  // a single high point has no ridge across the wider scales
+  #[test]
+  fn ignores_spikes() {
+    let mut pts = spectrum(&[(250, 100.0, 4.0)]);
+    pts[100].int += 200.0;
+    assert_eq!(found(&pts), vec![250]);
+  }
+
+  #[test]
+  fn short_spectra() {
+    let pts = spectrum(&[(5, 100.0, 1.5)]);
+    assert!(Cwt::default().detect(&pts[..2]).is_empty());
+    assert_eq!(found(&pts[..12]), vec![5]);
+  }
+}
@@ -0,0 +1,635 @@
+//! Reading, processing and writing mass spectra, without a user interface.
+//!
+//! A file is read into a [`Run`] with [`parser::parse_file`], which holds
+//! one or more [`Scan`]s. Turning scans into a [`Dataset`] finds the local
+//! maxima and their signal-to-noise ratios; peaks are then picked with
+//! [`Dataset::find_peaks`] or [`Dataset::find_peaks_cwt`] and written with
+//! [`writer::write_file`].
+//!
+//! ```no_run
+//! use moremass_core::{ load_dataset, writer::{ self, Format, Encoding } };
+//!
+//! let mut ds = load_dataset(&"spectrum.mzXML".to_string()).unwrap();
+//! ds.find_peaks(3.0, 0.0, 0.05, true);
+//! writer::write_file(&ds, &"peaks.csv".to_string(), Format::PeaksCsv, Encoding::default()).unwrap();
+//! ```
+
+pub mod parser;
+pub mod writer;
+pub mod project;
+pub mod calcs;
+pub mod baseline;
+pub mod smoothing;
+pub mod cwt;
+use chrono::{DateTime, TimeZone, Utc};
+use std::sync::atomic::{ AtomicU64, Ordering };
+use serde::{Serialize, Deserialize, Deserializer};
+
+use baseline::Baseline;
+use smoothing::Smoothing;
+use cwt::Cwt;
+use parser::{ ParseError, Progress };
+
+/// A single point of a spectrum
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct MSPoint {
+  pub mz:  f64,
+  pub int: f64,
+  pub snr: f64, // Signal/Noise Ratio; only neq 0 for points that are local maxima
+}
+
+/// The noise around an m/z value: the typical intensity there and how much it
+/// scatters
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Noise {
+  pub mz:    f64,
+  pub level: f64,
+  pub width: f64,
+}
+
+/// A picked peak. index is the point it was picked at, the other values are
+/// fitted around that point.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Peak {
+  pub index: usize,
+  pub mz:    f64, // of the apex, as found by the Centroiding
+  pub int:   f64,
+  pub fwhm:  f64, // full width at half maximum, in m/z; 0 if unknown
+  pub area:  f64,
+}
+
+impl Peak {
+  /// m/z over FWHM, 0 if the width is not known
+  pub fn resolution(&self) -> f64 {
+    if self.fwhm > 0.0 { self.mz / self.fwhm } else { 0.0 }
+  }
+}
+
+/// How the apex of a peak is located between the points
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Centroiding {
+  #[default]
+  Parabolic,  // vertex of a parabola through the highest point and its neighbours
+  Gaussian,   // the same on the logarithm of the intensity
+  HalfHeight, // intensity-weighted mean of the points above half height
+}
+
+impl Centroiding {
+  pub const ALL: [Centroiding; 3] = [
+    Centroiding::Parabolic,
+    Centroiding::Gaussian,
+    Centroiding::HalfHeight,
+  ];
+}
+
+impl std::fmt::Display for Centroiding {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        Centroiding::Parabolic  => "Parabolic Fit",
+        Centroiding::Gaussian   => "Gaussian Fit",
+        Centroiding::HalfHeight => "Half-Height Centroid",
+      }
+    )
+  }
+}
+
+/// A spectrum ready for processing. maxima are indices into points.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Dataset {
+  pub metadata: Metadata,
+  
+  pub points:  Vec<MSPoint>,
+  #[serde(deserialize_with = "read_peaks")]
+  pub peaks:   Vec<Peak>,
+  pub maxima:  Vec<usize>,
+  pub mz_min:  f64,
+  pub mz_max:  f64,
+  pub int_min: f64,
+  pub int_max: f64,
+  
+  // of the whole spectrum
+  pub noise_level: f64,
+  pub noise_width: f64,
+  
+  // estimated in windows of noise_window m/z; 0 picks a twentieth of the range
+  #[serde(default)]
+  pub noise:        Vec<Noise>,
+  #[serde(default)]
+  pub noise_window: f64,
+  
+  #[serde(default)]
+  pub centroiding: Centroiding,
+  
+  #[serde(default)]
+  pub annotations: Vec<Annotation>,
+  
+  pub visible: bool,
+  
+  // see Dataset::generation
+  #[serde(skip, default = "next_generation")]
+  generation: u64,
+}
+
+/// A label attached to a peak, e.g. an assigned fragment. Belongs to the point
+/// closest to its m/z.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Annotation {
+  pub mz:             f64,
+  pub label:          String,
+  pub charge:         Option<i32>,
+  pub formula:        String,
+  pub theoretical_mz: Option<f64>,
+}
+
+/// What is known about where a spectrum came from
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Metadata {
+  pub title:       String,
+  pub operator:    String,
+  pub contact:     String,
+  pub institution: String,
+  pub instrument:  String,
+  pub date:        DateTime<Utc>,
+  pub path:        String, 
+  pub source:      String, // file the data was originally converted from
+  pub processing:  Vec<String>,
+  #[serde(default)]
+  pub notes:       String,
+  
+  // only known for single MS/MS spectra
+  pub precursor_mz:   Option<f64>,
+  pub charge:         Option<i32>,
+  pub retention_time: Option<f64>, // in seconds
+}
+
+impl Default for Metadata {
+  fn default() -> Self {
+    Metadata {
+      title:       "".to_string(),
+      operator:    "".to_string(),
+      contact:     "".to_string(),
+      institution: "".to_string(),
+      instrument:  "".to_string(),
+      date:        Utc.timestamp_opt(0, 0).unwrap(),
+      path:        "".to_string(),
+      source:      "".to_string(),
+      processing:  vec![],
+      notes:       "".to_string(),
+      
+      precursor_mz:   None,
+      charge:         None,
+      retention_time: None,
+    }
+  }
+}
+
+/// A single spectrum as it was read from a file, before it becomes a Dataset
+#[derive(Clone, Default, Debug)]
+pub struct Scan {
+  pub num:            u32,
+  pub ms_level:       u32,
+  pub retention_time: Option<f64>, // in seconds
+  pub polarity:       String,
+  pub base_peak_mz:   Option<f64>,
+  pub tot_current:    Option<f64>,
+  pub precursor_mz:   Option<f64>,
+  pub charge:         Option<i32>,
+  pub title:          String, // MGF blocks carry their own
+  
+  pub points: Vec<MSPoint>,
+  
+  // peaks picked and annotated in the file, if the format stores them
+  pub peaks:       Vec<f64>,
+  pub annotations: Vec<Annotation>,
+  
+  // where the scan starts in the file, for scans that were listed from an
+  // index and whose points are only read when needed
+  pub offset: Option<u64>,
+}
+
+/// Everything read from one file
+#[derive(Clone, Debug)]
+pub struct Run {
+  pub metadata: Metadata,
+  pub scans:    Vec<Scan>,
+}
+
+impl Run {
+  /// Builds a Dataset from the scans numbered from..=to. Of a range, only the
+  /// scans with the same msLevel as the first one are summed up.
+  pub fn to_dataset(&self, from: u32, to: u32) -> Option<Dataset> {
+  
+    let in_range: Vec<&Scan> = 
+      self.scans.iter()
+        .filter(|scan| { scan.num >= from && scan.num <= to })
+        .collect();
+    
+    if in_range.is_empty() {
+      return None;
+    }
+    
+    let ms_level = in_range[0].ms_level;
+    let spectra: Vec<&Vec<MSPoint>> = 
+      in_range.iter()
+        .filter(|scan| { scan.ms_level == ms_level })
+        .map(|scan| { &scan.points })
+        .collect();
+    
+    let points = if spectra.len() == 1 {
+      spectra[0].clone()
+    } else {
+      calcs::sum_spectra(&spectra)
+    };
+    
+    if points.is_empty() {
+      return None;
+    }
+    
+    let mut md = self.metadata.clone();
+    if in_range.len() == 1 {
+      let scan = in_range[0];
+      md.precursor_mz   = scan.precursor_mz;
+      md.charge         = scan.charge;
+      md.retention_time = scan.retention_time;
+      
+      if !scan.title.is_empty() {
+        md.title = scan.title.clone();
+      }
+    }
+    if self.scans.len() > 1 && md.title == self.metadata.title {
+      md.title = if from == to {
+        format!("{} #{}", md.title, from)
+      } else {
+        format!("{} #{}-{}", md.title, from, to)
+      };
+    }
+    
+    let mut ds = Dataset::new(md, points);
+    
+    if in_range.len() == 1 {
+      for mz in &in_range[0].peaks {
+        if let Some(i) = ds.nearest_point(*mz) {
+          ds.pushpeak(i);
+        }
+      }
+      ds.annotations = in_range[0].annotations.clone();
+    }
+    
+    Some(ds)
+  }
+}
+
+impl Dataset {
+  /// Finds the local maxima of the points and their S/N. The points must be
+  /// sorted by m/z and not be empty.
+  pub fn new( md: Metadata, pts: Vec<MSPoint> ) -> Self {
+  
+    let mut ds = Dataset {
+      metadata: md,
+      points: pts,
+      peaks:  vec![],
+      maxima: vec![],
+      
+      mz_min:  0.0,
+      mz_max:  0.0,
+      int_min: 0.0,
+      int_max: 0.0,
+      
+      noise_level: 0.0,
+      noise_width: 0.0,
+      
+      noise:        vec![],
+      noise_window: 0.0,
+      
+      centroiding: Centroiding::default(),
+      
+      annotations: vec![],
+      
+      visible: true,
+      
+      generation: next_generation(),
+    };
+    ds.recompute();
+    ds
+  
+  }
+
+  /// Updates the ranges, the noise estimates, the maxima and their S/N after
+  /// the points or the noise window have changed. Picked peaks are kept.
+  pub fn recompute(&mut self) {
+  
+    let pts = &mut self.points;
+    
+    self.mz_min = pts[0].mz;
+    self.mz_max = pts[pts.len() - 1].mz;
+  
+    let mut buf: Vec<f64> =
+      pts.iter()
+        .map(|MSPoint {int, ..}| {*int})
+        .collect();
+    calcs::sort_in_place(&mut buf);
+    
+    self.int_min = buf[0];
+    self.int_max = buf[ buf.len() - 1 ];
+    (self.noise_level, self.noise_width) = calcs::noise_of(&mut buf);
+    
+    let window = if self.noise_window > 0.0 {
+      self.noise_window
+    } else {
+      (self.mz_max - self.mz_min) / 20.0
+    };
+    self.noise = calcs::estimate_noise(pts, window);
+    
+    // too few points for windows of their own
+    if self.noise.is_empty() {
+      self.noise = vec![Noise {
+        mz:    (self.mz_min + self.mz_max) / 2.0,
+        level: self.noise_level,
+        width: self.noise_width,
+      }];
+    }
+    
+    for pt in pts.iter_mut() {
+      pt.snr = 0.0;
+    }
+    self.maxima = calcs::get_maxima(pts);
+    calcs::update_snrs(pts, &self.maxima, &self.noise);
+    
+    self.refit_peaks();
+  
+  }
+
+  /// Estimates the noise in windows of the given m/z width instead, 0 for a
+  /// twentieth of the m/z range
+  pub fn set_noise_window(&mut self, window: f64) {
+    self.noise_window = window;
+    self.recompute();
+  }
+
+  /// Subtracts the estimated baseline, clipping at zero, and recomputes the
+  /// maxima and their S/N
+  pub fn subtract_baseline(&mut self, baseline: &Baseline) {
+  
+    let estimate = baseline.estimate(&self.points);
+    
+    for (pt, b) in self.points.iter_mut().zip(estimate.iter()) {
+      pt.int = f64::max(pt.int - b, 0.0);
+    }
+    
+    self.metadata.processing.push(baseline.describe());
+    self.recompute();
+  }
+
+  /// Replaces the intensities with smoothed ones and recomputes the maxima
+  /// and their S/N
+  pub fn smooth(&mut self, smoothing: &Smoothing) {
+  
+    let smoothed = smoothing.apply(&self.points);
+    
+    for (pt, int) in self.points.iter_mut().zip(smoothed.iter()) {
+      pt.int = *int;
+    }
+    
+    self.metadata.processing.push(smoothing.describe());
+    self.recompute();
+  }
+
+  /// Changes whenever the points or peaks are edited and is never the same
+  /// for two Datasets, so that work started on a copy can tell whether the
+  /// Dataset was changed or replaced in the meantime
+  pub fn generation(&self) -> u64 {
+    self.generation
+  }
+
+  /// Gives the Dataset a new generation after editing its fields directly
+  pub fn touch(&mut self) {
+    self.generation = next_generation();
+  }
+
+  /// Marks the point with index i as a peak
+  pub fn pushpeak(&mut self, i: usize) {
+    if self.points.len() > i && !self.peaks.iter().any(|p| { p.index == i }) {
+      self.peaks.push(calcs::fit_peak(&self.points, i, self.centroiding));
+      self.touch();
+    }
+  }
+  
+  /// Unmarks the peaks between lower and upper m/z
+  pub fn removepeaks(&mut self, lower: f64, upper: f64) {
+  
+    self.peaks = self.peaks.clone().into_iter().filter(
+      |p| {
+        p.mz < lower ||
+        p.mz > upper
+      }
+    ).collect();
+    self.touch();
+
+  }
+  
+  /// Fits the peaks again, e.g. after the points changed
+  pub fn refit_peaks(&mut self) {
+    let indices: Vec<usize> = self.peaks.iter()
+      .map(|p| { p.index })
+      .filter(|i| { *i < self.points.len() })
+      .collect();
+    
+    self.peaks = indices.into_iter()
+      .map(|i| { calcs::fit_peak(&self.points, i, self.centroiding) })
+      .collect();
+    self.touch();
+  }
+  
+  /// Locates the apexes of the peaks with another method
+  pub fn set_centroiding(&mut self, centroiding: Centroiding) {
+    self.centroiding = centroiding;
+    self.refit_peaks();
+  }
+
+  /// Index of the point whose m/z is closest to mz
+  pub fn nearest_point(&self, mz: f64) -> Option<usize> {
+  
+    if self.points.is_empty() {
+      return None;
+    }
+    
+    let i = self.points.partition_point(|pt| { pt.mz < mz });
+    
+    if i == 0 {
+      Some(0)
+    } else if i == self.points.len() {
+      Some(i - 1)
+    } else if self.points[i].mz - mz < mz - self.points[i-1].mz {
+      Some(i)
+    } else {
+      Some(i - 1)
+    }
+  }
+  
+  /// The annotation belonging to the point with index i, if any
+  pub fn annotation(&self, i: usize) -> Option<&Annotation> {
+    self.annotations.iter()
+      .find(|a| { self.nearest_point(a.mz) == Some(i) })
+  }
+  
+  /// Like annotation(), but ignores annotations further than tolerance away
+  pub fn annotation_within(&self, i: usize, tolerance: f64) -> Option<&Annotation> {
+    self.annotations.iter()
+      .find(|a| {
+        self.nearest_point(a.mz) == Some(i) && 
+        (a.mz - self.points[i].mz).abs() <= tolerance
+      })
+  }
+  
+  /// Picks the maxima whose S/N is above ratio and whose intensity is above
+  /// both abs_int and rel_int (a fraction of the highest intensity). With
+  /// overwrite, previously picked peaks are dropped first.
+  pub fn find_peaks(
+    &mut self, 
+    ratio:   f64, 
+    abs_int: f64, 
+    rel_int: f64,
+    overwrite: bool
+  ) {
+    if overwrite {
+      self.peaks = vec![];
+      self.touch();
+    }
+    
+    let threshold = f64::max(self.int_max * rel_int, abs_int);
+    
+    for i in self.maxima.clone() {
+      if self.points[i].int > threshold && 
+         self.points[i].snr > ratio {
+      
+        self.pushpeak(i);
+      }
+    }
+
+  }
+
+  /// Like find_peaks, but the candidates are the ridges found by a wavelet
+  /// transform, see [`cwt::Cwt::detect`], and ratio applies to their S/N
+  pub fn find_peaks_cwt(
+    &mut self,
+    cwt:     &Cwt,
+    ratio:   f64,
+    abs_int: f64,
+    rel_int: f64,
+    overwrite: bool
+  ) {
+    if overwrite {
+      self.peaks = vec![];
+      self.touch();
+    }
+
+    let threshold = f64::max(self.int_max * rel_int, abs_int);
+
+    for (i, snr) in cwt.detect(&self.points) {
+      if self.points[i].int > threshold && snr > ratio {
+        self.pushpeak(i);
+      }
+    }
+
+  }
+
+}
+
+// shared by all Datasets, so that no two get the same generation
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn next_generation() -> u64 {
+  GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+// Projects saved before peaks were fitted hold just the indices of the
+// peaks. Those come back unfitted, see Dataset::refit_peaks.
+fn read_peaks<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Peak>, D::Error> {
+
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum StoredPeak {
+    Index(usize),
+    Fitted(Peak),
+  }
+
+  let stored: Vec<StoredPeak> = Vec::deserialize(deserializer)?;
+  
+  Ok(
+    stored.into_iter()
+      .map(|p| {
+        match p {
+          StoredPeak::Index(i)  => Peak { index: i, ..Peak::default() },
+          StoredPeak::Fitted(p) => p,
+        }
+      })
+      .collect()
+  )
+}
+
+/// A collection of Datasets, e.g. those open in the GUI
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Data {
+  pub sets: Vec<Dataset>,
+  pub mz_min:  f64,
+  pub mz_max:  f64,
+  pub int_min: f64,
+  pub int_max: f64,
+  pub curr_ds: usize,
+}
+
+impl Data {
+  /// Adds a dataset, widening the ranges, and selects it
+  pub fn push(&mut self, ds: Option<Dataset>) {
+    if let Some(d) = ds {
+      if !self.sets.is_empty() {
+        self.mz_min  = f64::min(self.mz_min,  d.mz_min);
+        self.mz_max  = f64::max(self.mz_max,  d.mz_max);
+        self.int_min = f64::min(self.int_min, d.int_min);
+        self.int_max = f64::max(self.int_max, d.int_max);
+      } else {
+        self.mz_min  = d.mz_min;
+        self.mz_max  = d.mz_max;
+        self.int_min = d.int_min;
+        self.int_max = d.int_max;
+      }
+      self.sets.push(d);
+      self.curr_ds = self.sets.len() - 1;
+    }
+  }
+  
+  /// Recalculates the ranges over all datasets, after one of them changed
+  pub fn update_ranges(&mut self) {
+    let mut sets = self.sets.iter();
+    
+    if let Some(first) = sets.next() {
+      self.mz_min  = first.mz_min;
+      self.mz_max  = first.mz_max;
+      self.int_min = first.int_min;
+      self.int_max = first.int_max;
+    }
+    for d in sets {
+      self.mz_min  = f64::min(self.mz_min,  d.mz_min);
+      self.mz_max  = f64::max(self.mz_max,  d.mz_max);
+      self.int_min = f64::min(self.int_min, d.int_min);
+      self.int_max = f64::max(self.int_max, d.int_max);
+    }
+  }
+}
+
+/// Reads a file and sums up all of its scans of the first scan's MS level
+pub fn load_dataset( s: &String ) -> Result<Dataset, ParseError> {
+
+  let progress = Progress::default();
+  let mut run  = parser::parse_file(s, &progress)?;
+  
+  let nums = run.scans.iter().map(|scan| { scan.num });
+  let (from, to) = (nums.clone().min().unwrap_or(0), nums.max().unwrap_or(0));
+  
+  parser::load_scans(&mut run, from, to, &progress)?;
+  
+  run.to_dataset(from, to).ok_or(ParseError::EmptySpectrum)
+}
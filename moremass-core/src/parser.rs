@@ -0,0 +1,503 @@
+
+//! Reading spectra from mzXML, mzML, MGF, mMass .msd and plain text files.
+
+pub mod mzxml;
+pub mod mzml;
+pub mod text;
+pub mod mgf;
+pub mod msd;
+
+use crate::{ Metadata, Run, Scan };
+
+use std::fmt;
+use std::fs::{ self, File };
+use std::path::Path;
+use std::io::{ self, BufReader, Cursor, Read, Seek, SeekFrom };
+use std::sync::{
+  Arc,
+  atomic::{ AtomicBool, AtomicU64, Ordering },
+};
+
+use byteorder::{ ByteOrder, ReadBytesExt };
+use chrono::{ DateTime, Utc };
+use flate2::read::ZlibDecoder;
+use quick_xml::{
+  Reader,
+  events::{ Event, BytesStart },
+};
+
+/// The extensions parse_file knows, in lower case
+pub const EXTENSIONS: [&str; 10] = [
+  "mzxml", "mzml", "mgf", "msd", "txt", "csv", "tsv", "xy", "asc", "dat",
+];
+
+const CONFIG: base64::Config = base64::Config::new(base64::CharacterSet::Standard, true);
+
+/// Everything that can go wrong while reading a file
+#[derive(Debug)]
+pub enum ParseError {
+  Io(io::Error),
+  Base64(base64::DecodeError),
+  Decompress(String),
+  Xml(String),
+  Format(String),
+  EmptySpectrum,
+  UnsupportedEncoding(String),
+  Cancelled,
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::Io(why)                  => write!(f, "Could not read file: {}", why),
+      ParseError::Base64(why)              => write!(f, "Could not decode peak list: {}", why),
+      ParseError::Decompress(why)          => write!(f, "Could not decompress peak list: {}", why),
+      ParseError::Xml(why)                 => write!(f, "Malformed XML: {}", why),
+      ParseError::Format(why)              => write!(f, "Malformed file: {}", why),
+      ParseError::EmptySpectrum            => write!(f, "No peaks found"),
+      ParseError::UnsupportedEncoding(enc) => write!(f, "Unsupported peak encoding: {}", enc),
+      ParseError::Cancelled                => write!(f, "Cancelled"),
+    }
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+  fn from(why: io::Error) -> Self {
+    ParseError::Io(why)
+  }
+}
+
+impl From<base64::DecodeError> for ParseError {
+  fn from(why: base64::DecodeError) -> Self {
+    ParseError::Base64(why)
+  }
+}
+
+impl From<quick_xml::Error> for ParseError {
+  fn from(why: quick_xml::Error) -> Self {
+    match why {
+      quick_xml::Error::Io(e) => ParseError::Io(e),
+      _                       => ParseError::Xml(why.to_string()),
+    }
+  }
+}
+
+/// Shared between a running parser and whoever started it: tells how much of
+/// the file has been read, and lets the parser be stopped early.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+  read:      Arc<AtomicU64>,
+  total:     Arc<AtomicU64>,
+  cancelled: Arc<AtomicBool>,
+}
+
+impl Progress {
+  /// Between 0 and 1; 0 as long as the file size is not known
+  pub fn fraction(&self) -> f32 {
+    let total = self.total.load(Ordering::Relaxed);
+    if total == 0 {
+      0.0
+    } else {
+      f32::min(self.read.load(Ordering::Relaxed) as f32 / total as f32, 1.0)
+    }
+  }
+
+  /// Asks the parser to stop; it then fails with ParseError::Cancelled
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+}
+
+// A file that counts the bytes read from it, and fails once the Progress
+// it reports to is cancelled
+struct Tracked {
+  file:     File,
+  progress: Progress,
+}
+
+impl Read for Tracked {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.progress.is_cancelled() {
+      return Err(io::Error::other("cancelled"));
+    }
+    let n = self.file.read(buf)?;
+    self.progress.read.fetch_add(n as u64, Ordering::Relaxed);
+    Ok(n)
+  }
+}
+
+type XmlReader = Reader<BufReader<Tracked>>;
+
+/// Lists the scans of a file. Picks a reader based on the file extension;
+/// files with unknown extensions are identified by their first few bytes.
+///
+/// Scans of indexed mzXML and mzML files come without their points, which
+/// are read by load_scans.
+pub fn parse_file( s: &String, progress: &Progress ) -> Result<Run, ParseError> {
+
+  progress.total.store(fs::metadata(s)?.len(), Ordering::Relaxed);
+
+  let result = parse_any(s, progress);
+
+  // whatever went wrong, it was because the user asked for it
+  if progress.is_cancelled() {
+    Err(ParseError::Cancelled)
+  } else {
+    result
+  }
+}
+
+fn parse_any( s: &String, progress: &Progress ) -> Result<Run, ParseError> {
+
+  let ext = Path::new(s).extension()
+    .map(|e| { e.to_string_lossy().to_lowercase() })
+    .unwrap_or_default();
+
+  match ext.as_str() {
+    "mzml"  => mzml::parse_mzml(s, progress),
+    "mzxml" => mzxml::parse_mzxml(s, progress),
+    "mgf"   => mgf::parse_mgf(s, progress),
+    "msd"   => msd::parse_msd(s, progress),
+    "txt" | "csv" | "tsv" | "xy" | "asc" | "dat" => text::parse_text(s, progress),
+    _ => {
+      match sniff(s)? {
+        Format::MzML  => mzml::parse_mzml(s, progress),
+        Format::MzXML => mzxml::parse_mzxml(s, progress),
+        Format::Mgf   => mgf::parse_mgf(s, progress),
+        Format::Msd   => msd::parse_msd(s, progress),
+        Format::Text  => text::parse_text(s, progress),
+      }
+    }
+  }
+}
+
+/// Reads the points of the scans numbered from..=to that were only listed
+/// when the file was opened, see Scan.offset. Scans that already hold their
+/// points are left alone.
+pub fn load_scans(
+  run:      &mut Run,
+  from:     u32,
+  to:       u32,
+  progress: &Progress
+) -> Result<(), ParseError> {
+
+  let s = run.metadata.path.clone();
+
+  // a scan is assumed to reach up to where the next one starts
+  let mut offsets: Vec<u64> = run.scans.iter().filter_map(|scan| { scan.offset }).collect();
+  offsets.sort();
+  let len = fs::metadata(&s)?.len();
+
+  let pending: Vec<&mut Scan> =
+    run.scans.iter_mut()
+      .filter(|scan| {
+        scan.num >= from && scan.num <= to &&
+        scan.offset.is_some() && scan.points.is_empty()
+      })
+      .collect();
+
+  if pending.is_empty() {
+    return Ok(());
+  }
+
+  let total: u64 = pending.iter()
+    .filter_map(|scan| { scan.offset })
+    .map(|offset| {
+      offsets.iter().find(|next| { **next > offset }).unwrap_or(&len) - offset
+    })
+    .sum();
+  progress.total.store(total, Ordering::Relaxed);
+
+  let result = match sniff(&s)? {
+    Format::MzML => mzml::load_points(&s, pending, progress),
+    _            => mzxml::load_points(&s, pending, progress),
+  };
+
+  if progress.is_cancelled() {
+    Err(ParseError::Cancelled)
+  } else {
+    result
+  }
+}
+
+enum Format {
+  MzML,
+  MzXML,
+  Mgf,
+  Msd,
+  Text,
+}
+
+fn sniff( s: &String ) -> Result<Format, ParseError> {
+
+  let mut head = vec![0u8; 1024];
+  let n = File::open(s)?.read(&mut head)?;
+  let head = String::from_utf8_lossy(&head[..n]);
+
+  if head.contains("BEGIN IONS") {
+    Ok(Format::Mgf)
+  } else if head.contains("<mSD") {
+    Ok(Format::Msd)
+  } else if head.contains("<mzML") || head.contains("<indexedmzML") {
+    Ok(Format::MzML)
+  } else if head.contains("<mzXML") || head.trim_start().starts_with('<') {
+    Ok(Format::MzXML)
+  } else {
+    Ok(Format::Text)
+  }
+}
+
+// The parts of the instrument description that get folded into
+// Metadata.instrument
+#[derive(Default)]
+struct InstrumentInfo {
+  manufacturer: String,
+  model:        String,
+  ionisation:   String,
+  analyzer:     String,
+  detector:     String,
+}
+
+impl InstrumentInfo {
+  // e.g. "Bruker Daltonics autoflex (MALDI, TOF, MCP)"
+  fn describe(&self) -> String {
+    let name: Vec<&str> = 
+      [&self.manufacturer, &self.model].iter()
+        .filter(|s| { !s.is_empty() })
+        .map(|s| { s.as_str() })
+        .collect();
+    let parts: Vec<&str> = 
+      [&self.ionisation, &self.analyzer, &self.detector].iter()
+        .filter(|s| { !s.is_empty() })
+        .map(|s| { s.as_str() })
+        .collect();
+    
+    if parts.is_empty() {
+      name.join(" ")
+    } else {
+      format!("{} ({})", name.join(" "), parts.join(", ")).trim().to_string()
+    }
+  }
+}
+
+fn read_attributes(
+  tag:    &BytesStart,
+  reader: &XmlReader
+) -> Result<Vec<(Vec<u8>, String)>, ParseError> {
+
+  let mut attributes = vec![];
+
+  for maybe_attr in tag.attributes() {
+    let attr  = maybe_attr.map_err(quick_xml::Error::from)?;
+    let value = attr.unescape_and_decode_value(reader)?;
+    attributes.push((attr.key.to_vec(), value));
+  }
+
+  Ok(attributes)
+}
+
+// the Metadata every file gets before its contents are read: the file name
+// as title, the path, and the time the file was last written
+fn file_metadata( s: &String ) -> Metadata {
+
+  let path = Path::new(s);
+
+  Metadata {
+    title: match path.file_name() {
+      Some(name) => name.to_string_lossy().to_string(),
+      None       => s.clone(),
+    },
+    path: s.clone(),
+    date: match fs::metadata(path).and_then(|md| md.modified()) {
+      Ok(time) => DateTime::<Utc>::from(time),
+      Err(_)   => Metadata::default().date,
+    },
+    ..Metadata::default()
+  }
+}
+
+// decodes the base64 text of a binary block, which some converters wrap over
+// several lines
+fn decode_base64( text: &[u8] ) -> Result<Vec<u8>, ParseError> {
+  let text: Vec<u8> = text.iter().copied().filter(|b| { !b.is_ascii_whitespace() }).collect();
+  Ok(base64::decode_config(&text, CONFIG)?)
+}
+
+// inflates a zlib-compressed binary block. The expected compressed length
+// is optional in mzXML (0 means unknown), so it is only checked if given.
+fn decompress( bytes: &[u8], compressed_len: usize ) -> Result<Vec<u8>, ParseError> {
+
+  if compressed_len > 0 && compressed_len != bytes.len() {
+    return Err(ParseError::Decompress(format!(
+      "compressedLen is {}, but the block holds {} bytes",
+      compressed_len, bytes.len()
+    )));
+  }
+
+  let mut out = Vec::with_capacity(bytes.len() * 2);
+  match ZlibDecoder::new(bytes).read_to_end(&mut out) {
+    Err(why) => Err(ParseError::Decompress(why.to_string())),
+    Ok(_)    => Ok(out)
+  }
+}
+
+// reads a packed array of 32- or 64-bit floats
+fn read_floats<B: ByteOrder>( bytes: &Vec<u8>, width: usize ) -> Vec<f64> {
+
+  let n = bytes.len() / width;
+  let mut values: Vec<f64> = Vec::with_capacity(n);
+  let mut cursor           = Cursor::new(bytes);
+
+  for _i in 0 .. n {
+    values.push(if width == 4 {
+      cursor.read_f32::<B>().unwrap_or(0.0) as f64
+    } else {
+      cursor.read_f64::<B>().unwrap_or(0.0)
+    });
+  }
+
+  values
+}
+
+// how far from the end of the file the offset of the index is looked for
+const TAIL_LEN: u64 = 4096;
+
+// Reads the byte offsets listed in the <index name="..."> of indexed mzXML
+// and mzML files. offset_tag is the element at the end of the file that
+// tells where the index starts. Returns None for files without an index.
+fn read_index(
+  s:          &String,
+  offset_tag: &str,
+  name:       &str,
+  progress:   &Progress
+) -> Result<Option<Vec<u64>>, ParseError> {
+
+  let mut file = File::open(s)?;
+  let len = file.metadata()?.len();
+
+  let mut tail = vec![];
+  file.seek(SeekFrom::Start(len.saturating_sub(TAIL_LEN)))?;
+  file.read_to_end(&mut tail)?;
+  let tail = String::from_utf8_lossy(&tail);
+
+  let open  = format!("<{}>", offset_tag);
+  let close = format!("</{}>", offset_tag);
+
+  let index_offset = match tail.find(&open) {
+    None        => { return Ok(None); }
+    Some(start) => {
+      let rest = &tail[start + open.len()..];
+      match rest.find(&close).and_then(|end| { rest[..end].trim().parse::<u64>().ok() }) {
+        Some(offset) if offset > 0 && offset < len => offset,
+        _ => { return Ok(None); }
+      }
+    }
+  };
+
+  let mut reader = reader_at(s, index_offset, progress)?;
+  let mut buf = Vec::new();
+
+  let mut offsets = vec![];
+  let mut in_index  = false;
+  let mut in_offset = false;
+
+  loop {
+
+    buf.clear();
+    match reader.read_event(&mut buf) {
+
+      Ok(Event::Start(ref tag)) if tag.name() == b"index" => {
+        in_index = read_attributes(tag, &reader)?.iter()
+          .any(|(key, value)| { key.as_slice() == b"name" && value == name });
+      }
+
+      Ok(Event::Start(ref tag)) if tag.name() == b"offset" => {
+        in_offset = in_index;
+      }
+
+      Ok(Event::Text(ref txt)) if in_offset => {
+        match String::from_utf8_lossy(txt).trim().parse::<u64>() {
+          Ok(offset) => { offsets.push(offset); }
+          Err(_)     => { return Ok(None); }
+        }
+      }
+
+      Ok(Event::End(ref tag)) if tag.name() == b"offset" => {
+        in_offset = false;
+      }
+
+      Ok(Event::End(ref tag)) if tag.name() == b"index" && in_index => {
+        break;
+      }
+
+      // not an index after all, or an index without the wanted list
+      Ok(Event::Eof) | Err(_) => {
+        return Ok(None);
+      }
+
+      _ => {}
+    }
+  }
+
+  if offsets.is_empty() {
+    Ok(None)
+  } else {
+    Ok(Some(offsets))
+  }
+}
+
+// opens a file for reading, reporting to progress
+fn open( s: &String, progress: &Progress ) -> Result<Tracked, ParseError> {
+  Ok(Tracked {
+    file:     File::open(s)?,
+    progress: progress.clone(),
+  })
+}
+
+// opens a file that is read from start to end, e.g. a project, with its size
+// as the total of progress
+pub(crate) fn open_whole( s: &String, progress: &Progress ) -> Result<impl Read, ParseError> {
+  progress.total.store(fs::metadata(s)?.len(), Ordering::Relaxed);
+  open(s, progress)
+}
+
+// an XML reader that starts at the given byte offset of the file
+fn reader_at( s: &String, offset: u64, progress: &Progress ) -> Result<XmlReader, ParseError> {
+
+  let mut file = open(s, progress)?;
+  file.file.seek(SeekFrom::Start(offset))?;
+
+  let mut reader = Reader::from_reader(BufReader::new(file));
+  reader.trim_text(true);
+  reader.check_end_names(false);
+
+  Ok(reader)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  // Writes contents to a file in the temp directory. name, which ends in
+  // the extension, keeps the files of different tests apart.
+  pub fn temp_file( name: &str, contents: &[u8] ) -> String {
+    let s = std::env::temp_dir()
+      .join(format!("moremass_{}_{}", std::process::id(), name))
+      .to_string_lossy()
+      .to_string();
+    std::fs::write(&s, contents).unwrap();
+    s
+  }
+
+  #[test]
+  fn decodes_wrapped_base64() {
+    let wrapped = b"AAEC\r\n  AwQF\n\tBgc=\n";
+    assert_eq!(decode_base64(wrapped).unwrap(), vec![0, 1, 2, 3, 4, 5, 6, 7]);
+  }
+}
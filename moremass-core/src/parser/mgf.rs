@@ -0,0 +1,192 @@
+
+use crate::{ MSPoint, Run, Scan };
+use crate::parser::{ ParseError, Progress, file_metadata, open };
+
+use std::io::{ BufRead, BufReader };
+
+// Reads Mascot Generic Format peak lists. Every BEGIN IONS ... END IONS block
+// becomes one scan; parameters given before the first block apply to all.
+pub fn parse_mgf( s: &String, progress: &Progress ) -> Result<Run, ParseError> {
+
+  let file = open(s, progress)?;
+
+  let mut scans: Vec<Scan> = vec![];
+  let mut defaults = Scan::default();
+  let mut current: Option<Scan> = None;
+
+  for maybe_line in BufReader::new(file).lines() {
+
+    let line = maybe_line?;
+    let line = line.trim();
+
+    if line.is_empty() || is_comment(line) {
+      continue;
+    }
+
+    if line == "BEGIN IONS" {
+      let mut scan = defaults.clone();
+      scan.num      = scans.len() as u32 + 1;
+      scan.ms_level = 2;
+      current = Some(scan);
+      continue;
+    }
+
+    if line == "END IONS" {
+      match current.take() {
+        None => {
+          return Err(ParseError::Format(
+            "END IONS without matching BEGIN IONS".to_string()
+          ));
+        }
+        Some(mut scan) => {
+          scan.points.sort_by(|a, b| { a.mz.partial_cmp(&b.mz).unwrap() });
+          scans.push(scan);
+        }
+      }
+      continue;
+    }
+
+    let scan = current.as_mut().unwrap_or(&mut defaults);
+
+    if let Some((key, value)) = line.split_once('=') {
+      read_parameter(scan, key.trim(), value.trim());
+    } else if let Some(pt) = parse_peak(line) {
+      scan.points.push(pt);
+    }
+  }
+
+  scans.retain(|scan| !scan.points.is_empty());
+
+  if scans.is_empty() {
+    return Err(ParseError::EmptySpectrum);
+  }
+
+  Ok(Run {
+    metadata: file_metadata(s),
+    scans,
+  })
+}
+
+fn is_comment( line: &str ) -> bool {
+  line.starts_with('#') ||
+  line.starts_with(';') ||
+  line.starts_with('!') ||
+  line.starts_with('/')
+}
+
+fn read_parameter( scan: &mut Scan, key: &str, value: &str ) {
+
+  match key.to_uppercase().as_str() {
+    "TITLE" => {
+      scan.title = value.to_string();
+    }
+    // the precursor intensity may follow the m/z
+    "PEPMASS" => {
+      scan.precursor_mz = value.split_whitespace().next()
+        .and_then(|v| { v.parse().ok() });
+    }
+    "CHARGE" => {
+      scan.charge = parse_charge(value);
+    }
+    "RTINSECONDS" => {
+      // may be a range, "123.4-125.0"; the start is used then
+      scan.retention_time = value.split('-').next()
+        .and_then(|v| { v.trim().parse().ok() });
+    }
+    _ => {}
+  }
+}
+
+// charges look like "2+", "3-" or "2+ and 3+"; only the first one is kept
+fn parse_charge( value: &str ) -> Option<i32> {
+
+  let first = value.split(|c: char| { c == ',' || c.is_whitespace() }).next()?;
+
+  if let Some(n) = first.strip_suffix('+') {
+    n.parse().ok()
+  } else if let Some(n) = first.strip_suffix('-') {
+    n.parse::<i32>().ok().map(|n| { -n })
+  } else {
+    first.parse().ok()
+  }
+}
+
+fn parse_peak( line: &str ) -> Option<MSPoint> {
+
+  let mut fields = line.split_whitespace();
+
+  let mz: f64  = fields.next()?.parse().ok()?;
+  let int: f64 = fields.next().and_then(|v| { v.parse().ok() }).unwrap_or(0.0);
+
+  // NaN and inf parse, but cannot be sorted or plotted
+  if mz.is_finite() && int.is_finite() {
+    Some(MSPoint { mz, int, snr: 0.0 })
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::tests::temp_file;
+
+  fn read( name: &str, content: &str ) -> Result<Run, ParseError> {
+    let s   = temp_file(name, content.as_bytes());
+    let run = parse_mgf(&s, &Progress::default());
+    std::fs::remove_file(&s).unwrap();
+    run
+  }
+
+  #[test]
+  fn reads_blocks() {
+    let run = read("blocks.mgf", "\
+      # global parameters apply to every block\n\
+      CHARGE=2+\n\
+      BEGIN IONS\n\
+      TITLE=first\n\
+      PEPMASS=812.4 15000\n\
+      RTINSECONDS=61.5-63.0\n\
+      301.2 40\n\
+      200.1\t10\n\
+      END IONS\n\
+      \n\
+      BEGIN IONS\n\
+      TITLE=second\n\
+      CHARGE=3-\n\
+      150.0 5\n\
+      END IONS\n\
+      BEGIN IONS\n\
+      TITLE=empty\n\
+      END IONS\n"
+    ).unwrap();
+
+    assert_eq!(run.scans.len(), 2);
+
+    let first = &run.scans[0];
+    assert_eq!(first.title, "first");
+    assert_eq!(first.ms_level, 2);
+    assert_eq!(first.precursor_mz, Some(812.4));
+    assert_eq!(first.charge, Some(2));
+    assert_eq!(first.retention_time, Some(61.5));
+    let points: Vec<(f64, f64)> = first.points.iter().map(|pt| { (pt.mz, pt.int) }).collect();
+    assert_eq!(points, vec![(200.1, 10.0), (301.2, 40.0)]);
+
+    assert_eq!(run.scans[1].num, 2);
+    assert_eq!(run.scans[1].charge, Some(-3));
+  }
+
+  #[test]
+  fn skips_non_finite_peaks() {
+    let run = read("nan.mgf", "BEGIN IONS\n100 1\n101 nan\ninf 3\n102\nEND IONS\n").unwrap();
+
+    let points: Vec<(f64, f64)> = run.scans[0].points.iter().map(|pt| { (pt.mz, pt.int) }).collect();
+    assert_eq!(points, vec![(100.0, 1.0), (102.0, 0.0)]);
+  }
+
+  #[test]
+  fn rejects_unmatched_end() {
+    assert!(matches!(read("unmatched.mgf", "100 1\nEND IONS\n"), Err(ParseError::Format(_))));
+    assert!(matches!(read("nothing.mgf", "TITLE=x\n"), Err(ParseError::EmptySpectrum)));
+  }
+}
@@ -0,0 +1,462 @@
+
+use crate::{ Annotation, Metadata, MSPoint, Run, Scan };
+use crate::parser::{
+  ParseError,
+  XmlReader, Progress,
+  read_attributes, decode_base64, decompress, read_floats, file_metadata, reader_at,
+};
+
+use byteorder::{ BigEndian, LittleEndian };
+use chrono::{ DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc };
+use quick_xml::events::{ Event, BytesStart };
+
+// Describes an <mzArray> or <intArray>
+struct ArrayEncoding {
+  precision:   String,
+  compression: String,
+  endian:      String,
+}
+
+impl ArrayEncoding {
+  fn from_tag( tag: &BytesStart, reader: &XmlReader ) -> Result<Self, ParseError> {
+
+    let mut enc = ArrayEncoding {
+      precision:   "32".to_string(),
+      compression: String::new(),
+      endian:      "little".to_string(),
+    };
+
+    for (key, value) in read_attributes(tag, reader)? {
+      match key.as_slice() {
+        b"precision"   => { enc.precision   = value; }
+        b"compression" => { enc.compression = value; }
+        b"endian"      => { enc.endian      = value; }
+        _ => {}
+      }
+    }
+
+    Ok(enc)
+  }
+
+  fn decode( &self, content: &[u8] ) -> Result<Vec<f64>, ParseError> {
+
+    let mut bytes = decode_base64(content)?;
+
+    if self.compression == "zlib" {
+      bytes = decompress(&bytes, 0)?;
+    }
+
+    match (self.precision.as_str(), self.endian.as_str()) {
+      ("32", "little") => Ok(read_floats::<LittleEndian>(&bytes, 4)),
+      ("32", "big")    => Ok(read_floats::<BigEndian>(&bytes, 4)),
+      ("64", "little") => Ok(read_floats::<LittleEndian>(&bytes, 8)),
+      ("64", "big")    => Ok(read_floats::<BigEndian>(&bytes, 8)),
+      _ => Err(ParseError::UnsupportedEncoding(format!(
+        "precision {}, byte order {}", self.precision, self.endian
+      )))
+    }
+  }
+}
+
+// Reads documents saved by mMass (mSD 2.x). Besides the spectrum these hold
+// the picked peaks, their annotations and free-text notes. Sequences and
+// their matches have no counterpart here and are skipped.
+pub fn parse_msd( s: &String, progress: &Progress ) -> Result<Run, ParseError> {
+
+  let mut reader = reader_at(s, 0, progress)?;
+
+  let mut buf = Vec::new();
+
+  let mut metadata = file_metadata(s);
+  let mut scan = Scan {
+    num:      1,
+    ms_level: 1,
+    ..Scan::default()
+  };
+
+  // names of the currently open elements
+  let mut stack: Vec<Vec<u8>> = vec![];
+  // the text of the element currently being read
+  let mut content: Vec<u8> = vec![];
+
+  let mut encoding: Option<ArrayEncoding> = None;
+  let mut mzs:         Vec<f64> = vec![];
+  let mut intensities: Vec<f64> = vec![];
+
+  // peaks are kept with their intensity, in case there is no spectrum
+  let mut peaks: Vec<MSPoint> = vec![];
+  let mut annotation: Option<Annotation> = None;
+
+  loop {
+
+    buf.clear();
+    match reader.read_event(&mut buf) {
+
+      Ok(Event::Start(ref tag)) => {
+        match tag.name() {
+          b"mSD" => {
+            check_version(tag, &reader)?;
+          }
+          b"spectrum" => {
+            read_spectrum(tag, &reader, &mut scan)?;
+          }
+          b"mzArray" | b"intArray" => {
+            encoding = Some(ArrayEncoding::from_tag(tag, &reader)?);
+          }
+          b"annotation" => {
+            annotation = Some(read_annotation(tag, &reader)?);
+          }
+          _ => {
+            read_description(tag, &reader, &stack, &mut metadata)?;
+          }
+        }
+        stack.push(tag.name().to_vec());
+        content.clear();
+      }
+
+      Ok(Event::Empty(ref tag)) => {
+        match tag.name() {
+          b"mSD" => {
+            check_version(tag, &reader)?;
+          }
+          b"spectrum" => {
+            read_spectrum(tag, &reader, &mut scan)?;
+          }
+          b"peak" => {
+            if let Some(pt) = read_peak(tag, &reader)? {
+              peaks.push(pt);
+            }
+          }
+          b"annotation" => {
+            scan.annotations.push(read_annotation(tag, &reader)?);
+          }
+          _ => {
+            read_description(tag, &reader, &stack, &mut metadata)?;
+          }
+        }
+      }
+
+      Ok(Event::Text(ref txt)) => {
+        content.extend_from_slice(&txt.unescaped()?);
+      }
+
+      Ok(Event::CData(ref txt)) => {
+        content.extend_from_slice(txt);
+      }
+
+      Ok(Event::End(ref tag)) => {
+        stack.pop();
+        let in_description = stack.last().map(|name| { name.as_slice() }) == Some(b"description");
+
+        match tag.name() {
+          b"mzArray" | b"intArray" => {
+            if let Some(enc) = encoding.take() {
+              let values = if content.is_empty() { vec![] } else { enc.decode(&content)? };
+              if tag.name() == b"mzArray" {
+                mzs = values;
+              } else {
+                intensities = values;
+              }
+            }
+          }
+          b"annotation" => {
+            if let Some(mut a) = annotation.take() {
+              a.label = String::from_utf8_lossy(&content).trim().to_string();
+              scan.annotations.push(a);
+            }
+          }
+          b"title" if in_description => {
+            metadata.title = String::from_utf8_lossy(&content).trim().to_string();
+          }
+          b"notes" if in_description => {
+            metadata.notes = String::from_utf8_lossy(&content).trim().to_string();
+          }
+          _ => {}
+        }
+        content.clear();
+      }
+
+      Ok(Event::Eof) => { break; }
+
+      Err(why) => {
+        return Err(ParseError::Xml(format!(
+          "{} at position {}",
+          why, reader.buffer_position()
+        )));
+      }
+
+      _ => {}
+    }
+  }
+
+  if mzs.len() != intensities.len() {
+    return Err(ParseError::Format(format!(
+      "{} m/z values, but {} intensities",
+      mzs.len(), intensities.len()
+    )));
+  }
+
+  peaks.sort_by(|a, b| { a.mz.partial_cmp(&b.mz).unwrap() });
+  scan.peaks = peaks.iter().map(|pt| { pt.mz }).collect();
+
+  // documents made from peak lists have no profile; the peaks are the
+  // spectrum. NaN and inf decode like any other value, but cannot be sorted
+  // or plotted.
+  scan.points = if mzs.is_empty() {
+    peaks
+  } else {
+    mzs.iter().zip(intensities.iter())
+      .filter(|(mz, int)| { mz.is_finite() && int.is_finite() })
+      .map(|(mz, int)| { MSPoint { mz: *mz, int: *int, snr: 0.0 } })
+      .collect()
+  };
+
+  if scan.points.is_empty() {
+    return Err(ParseError::EmptySpectrum);
+  }
+
+  Ok(Run {
+    metadata,
+    scans:    vec![scan],
+  })
+}
+
+// mSD 1.x stored the spectrum in a different layout
+fn check_version( tag: &BytesStart, reader: &XmlReader ) -> Result<(), ParseError> {
+
+  for (key, value) in read_attributes(tag, reader)? {
+    if key.as_slice() == b"version" && value.starts_with("1.") {
+      return Err(ParseError::Format(format!("mSD version {} is not supported", value)));
+    }
+  }
+
+  Ok(())
+}
+
+// the elements inside <description> hold their value in an attribute,
+// except for <title> and <notes>
+fn read_description(
+  tag:    &BytesStart,
+  reader: &XmlReader,
+  stack:  &[Vec<u8>],
+  md:     &mut Metadata
+) -> Result<(), ParseError> {
+
+  if stack.last().map(|name| { name.as_slice() }) != Some(b"description") {
+    return Ok(());
+  }
+
+  let value = read_attributes(tag, reader)?.into_iter()
+    .find(|(key, _)| { key.as_slice() == b"value" })
+    .map(|(_, value)| { value })
+    .unwrap_or_default();
+
+  match tag.name() {
+    b"operator"    => { md.operator    = value; }
+    b"contact"     => { md.contact     = value; }
+    b"institution" => { md.institution = value; }
+    b"instrument"  => { md.instrument  = value; }
+    b"date" => {
+      if let Some(date) = parse_date(&value) {
+        md.date = date;
+      }
+    }
+    _ => {}
+  }
+
+  Ok(())
+}
+
+fn read_spectrum(
+  tag:    &BytesStart,
+  reader: &XmlReader,
+  scan:   &mut Scan
+) -> Result<(), ParseError> {
+
+  for (key, value) in read_attributes(tag, reader)? {
+    match key.as_slice() {
+      b"scanNumber"      => { scan.num            = value.parse().unwrap_or(1); }
+      b"msLevel"         => { scan.ms_level       = value.parse().unwrap_or(1); }
+      b"retentionTime"   => { scan.retention_time = value.parse().ok(); }
+      b"precursorMZ"     => { scan.precursor_mz   = value.parse().ok(); }
+      b"precursorCharge" => { scan.charge         = value.parse().ok(); }
+      b"polarity" => {
+        scan.polarity = match value.as_str() {
+          "1"  => "+".to_string(),
+          "-1" => "-".to_string(),
+          _    => String::new(),
+        };
+      }
+      _ => {}
+    }
+  }
+
+  Ok(())
+}
+
+// older documents call the intensity "ai" (absolute intensity)
+fn read_peak( tag: &BytesStart, reader: &XmlReader ) -> Result<Option<MSPoint>, ParseError> {
+
+  let mut mz:  Option<f64> = None;
+  let mut int: f64 = 0.0;
+  let mut snr: f64 = 0.0;
+
+  for (key, value) in read_attributes(tag, reader)? {
+    match key.as_slice() {
+      b"mz"                => { mz  = value.parse().ok(); }
+      b"intensity" | b"ai" => { int = value.parse().unwrap_or(0.0); }
+      b"sn"                => { snr = value.parse().unwrap_or(0.0); }
+      _ => {}
+    }
+  }
+
+  // NaN and inf parse, but cannot be sorted or plotted
+  Ok(mz.filter(|mz| { mz.is_finite() && int.is_finite() }).map(|mz| { MSPoint { mz, int, snr } }))
+}
+
+// the label is the element's text, read once the element ends
+fn read_annotation( tag: &BytesStart, reader: &XmlReader ) -> Result<Annotation, ParseError> {
+
+  let mut annotation = Annotation::default();
+
+  for (key, value) in read_attributes(tag, reader)? {
+    match key.as_slice() {
+      b"peakMZ"  => { annotation.mz      = value.parse().unwrap_or(0.0); }
+      b"charge"  => { annotation.charge  = value.parse().ok(); }
+      b"formula" => { annotation.formula = value; }
+      b"theoretical" | b"theoreticalMZ" => {
+        annotation.theoretical_mz = value.parse().ok();
+      }
+      _ => {}
+    }
+  }
+
+  Ok(annotation)
+}
+
+// mMass writes dates as plain text, usually "2011-03-12 14:03:11"
+fn parse_date( s: &str ) -> Option<DateTime<Utc>> {
+
+  if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+    return Some(date.with_timezone(&Utc));
+  }
+
+  for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%d.%m.%Y %H:%M:%S"] {
+    if let Ok(date) = NaiveDateTime::parse_from_str(s, format) {
+      return Some(Utc.from_utc_datetime(&date));
+    }
+  }
+
+  for format in ["%Y-%m-%d", "%d.%m.%Y"] {
+    if let Ok(date) = NaiveDate::parse_from_str(s, format) {
+      return Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?));
+    }
+  }
+
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::tests::temp_file;
+
+  use byteorder::WriteBytesExt;
+
+  // an array as the base64 of little-endian singles
+  fn encode( values: &[f32] ) -> String {
+    let mut bytes = vec![];
+    for v in values {
+      bytes.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    base64::encode(bytes)
+  }
+
+  fn read( name: &str, body: &str ) -> Result<Run, ParseError> {
+    let s = temp_file(name, format!(
+      "<?xml version=\"1.0\" encoding=\"utf-8\" ?>\n<mSD version=\"2.2\">\n{}\n</mSD>\n", body
+    ).as_bytes());
+    let run = parse_msd(&s, &Progress::default());
+    std::fs::remove_file(&s).unwrap();
+    run
+  }
+
+  #[test]
+  fn reads_document() {
+    let run = read("document.msd", &format!(
+      "<description>\n\
+       <title>BSA digest</title>\n\
+       <date value=\"2011-03-12 14:03:11\" />\n\
+       <operator value=\"Jane Doe\" />\n\
+       <instrument value=\"autoflex\" />\n\
+       <notes><![CDATA[first line\nsecond line]]></notes>\n\
+       </description>\n\
+       <spectrum points=\"3\" scanNumber=\"5\" msLevel=\"2\" precursorMZ=\"812.4\" polarity=\"1\">\n\
+       <mzArray precision=\"32\" compression=\"\" endian=\"little\">{}</mzArray>\n\
+       <intArray precision=\"32\" compression=\"\" endian=\"little\">{}</intArray>\n\
+       </spectrum>\n\
+       <peaklist>\n\
+       <peak mz=\"101.0\" ai=\"30.0\" sn=\"12.5\" />\n\
+       <peak mz=\"100.5\" ai=\"20.0\" sn=\"8.0\" />\n\
+       </peaklist>\n\
+       <annotations>\n\
+       <annotation peakMZ=\"101.0\" charge=\"1\" formula=\"C2H5\">y1</annotation>\n\
+       </annotations>",
+      encode(&[100.5, 101.0, 101.5]), encode(&[20.0, 30.0, 4.0])
+    )).unwrap();
+
+    let md = &run.metadata;
+    assert_eq!(md.title, "BSA digest");
+    assert_eq!(md.operator, "Jane Doe");
+    assert_eq!(md.instrument, "autoflex");
+    assert_eq!(md.notes, "first line\nsecond line");
+    assert_eq!(md.date.to_rfc3339(), "2011-03-12T14:03:11+00:00");
+
+    let scan = &run.scans[0];
+    assert_eq!(scan.num, 5);
+    assert_eq!(scan.ms_level, 2);
+    assert_eq!(scan.precursor_mz, Some(812.4));
+    assert_eq!(scan.polarity, "+");
+    assert_eq!(scan.points.len(), 3);
+    assert_eq!(scan.peaks, vec![100.5, 101.0]);
+    assert_eq!(scan.annotations.len(), 1);
+    assert_eq!(scan.annotations[0].label, "y1");
+    assert_eq!(scan.annotations[0].charge, Some(1));
+  }
+
+  // without a spectrum the peaks make up the points
+  #[test]
+  fn reads_peak_list() {
+    let run = read("peaks.msd", "<peaklist>\n\
+      <peak mz=\"200.0\" intensity=\"5.0\" />\n\
+      <peak mz=\"nan\" intensity=\"7.0\" />\n\
+      <peak mz=\"150.0\" intensity=\"inf\" />\n\
+      <peak mz=\"100.0\" intensity=\"3.0\" />\n\
+      </peaklist>").unwrap();
+
+    let points: Vec<(f64, f64)> = run.scans[0].points.iter().map(|pt| { (pt.mz, pt.int) }).collect();
+    assert_eq!(points, vec![(100.0, 3.0), (200.0, 5.0)]);
+  }
+
+  #[test]
+  fn drops_non_finite_points() {
+    let run = read("nan.msd", &format!(
+      "<spectrum points=\"3\">\n\
+       <mzArray precision=\"32\" endian=\"little\">{}</mzArray>\n\
+       <intArray precision=\"32\" endian=\"little\">{}</intArray>\n\
+       </spectrum>",
+      encode(&[100.0, 101.0, 102.0]), encode(&[1.0, f32::NAN, 3.0])
+    )).unwrap();
+
+    let mzs: Vec<f64> = run.scans[0].points.iter().map(|pt| { pt.mz }).collect();
+    assert_eq!(mzs, vec![100.0, 102.0]);
+  }
+
+  #[test]
+  fn rejects_version_1() {
+    let s = temp_file("old.msd", b"<?xml version=\"1.0\"?>\n<mSD version=\"1.0\"></mSD>\n");
+    let run = parse_msd(&s, &Progress::default());
+    std::fs::remove_file(&s).unwrap();
+    assert!(matches!(run, Err(ParseError::Format(_))));
+  }
+}
@@ -0,0 +1,682 @@
+
+use crate::{ Metadata, MSPoint, Run, Scan };
+use crate::parser::{
+  ParseError, InstrumentInfo,
+  read_attributes, decode_base64, decompress, read_floats, file_metadata,
+  XmlReader, Progress, read_index, reader_at,
+};
+
+use byteorder::LittleEndian;
+use chrono::{ DateTime, NaiveDateTime, TimeZone, Utc };
+use quick_xml::events::{ Event, BytesStart };
+
+// A controlled vocabulary term, which is how mzML stores almost everything
+#[derive(Debug, Clone, Default)]
+struct CvParam {
+  accession: String,
+  name:      String,
+  value:     String,
+  unit:      String,
+}
+
+impl CvParam {
+  fn from_tag( tag: &BytesStart, reader: &XmlReader ) -> Result<Self, ParseError> {
+
+    let mut param = CvParam::default();
+
+    for (key, value) in read_attributes(tag, reader)? {
+      match key.as_slice() {
+        b"accession"     => { param.accession = value; }
+        b"name"          => { param.name      = value; }
+        b"value"         => { param.value     = value; }
+        b"unitAccession" => { param.unit      = value; }
+        _ => {}
+      }
+    }
+
+    Ok(param)
+  }
+}
+
+// Describes a <binaryDataArray>. mzML arrays are always little-endian.
+#[derive(Debug, Clone)]
+struct ArrayEncoding {
+  width:       usize,
+  compressed:  bool,
+  kind:        ArrayKind,
+  unsupported: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArrayKind {
+  Mz,
+  Intensity,
+  Other,
+}
+
+impl Default for ArrayEncoding {
+  fn default() -> Self {
+    ArrayEncoding {
+      width:       8,
+      compressed:  false,
+      kind:        ArrayKind::Other,
+      unsupported: None,
+    }
+  }
+}
+
+#[derive(Default)]
+struct State {
+  // names of the currently open elements
+  stack: Vec<Vec<u8>>,
+
+  instrument:       InstrumentInfo,
+  param_groups:     Vec<(String, Vec<CvParam>)>,
+  software_version: String,
+
+  // the spectrum and array currently being read
+  scan:        Option<Scan>,
+  encoding:    ArrayEncoding,
+  mzs:         Vec<f64>,
+  intensities: Vec<f64>,
+  in_binary:   bool,
+  content:     Vec<u8>,
+
+  // stop once the first spectrum has been read
+  single: bool,
+}
+
+pub fn parse_mzml( s: &String, progress: &Progress ) -> Result<Run, ParseError> {
+
+  // indexed files let us list the spectra without decoding their arrays. If
+  // the index turns out to be wrong, the whole file is read after all.
+  if let Some(offsets) = read_index(s, "indexListOffset", "spectrum", progress)? {
+    if let Ok(run) = list_spectra(s, &offsets, progress) {
+      return Ok(run);
+    }
+  }
+
+  let mut reader = reader_at(s, 0, progress)?;
+
+  let mut metadata = file_metadata(s);
+  let mut scans: Vec<Scan> = vec![];
+  let mut state = State::default();
+
+  read_events(&mut reader, &mut state, &mut metadata, &mut scans, None)?;
+
+  scans.retain(|scan| !scan.points.is_empty());
+
+  if scans.is_empty() {
+    return Err(ParseError::EmptySpectrum);
+  }
+
+  metadata.instrument = state.instrument.describe();
+
+  Ok(Run {
+    metadata,
+    scans,
+  })
+}
+
+// Lists the spectra found at the given offsets, reading everything but their
+// binary arrays. The arrays are read by load_points once they are needed.
+fn list_spectra( s: &String, offsets: &Vec<u64>, progress: &Progress ) -> Result<Run, ParseError> {
+
+  let mut metadata = file_metadata(s);
+  let mut state = read_header(s, &mut metadata, progress)?;
+  let mut scans: Vec<Scan> = vec![];
+
+  for offset in offsets {
+
+    let mut reader = spectrum_at(s, *offset, &mut state, &mut metadata, progress)?;
+
+    read_events(&mut reader, &mut state, &mut metadata, &mut scans, Some(b"binaryDataArrayList"))?;
+
+    if let Some(mut scan) = state.scan.take() {
+      scan.offset = Some(*offset);
+      scans.push(scan);
+    }
+  }
+
+  if scans.is_empty() {
+    return Err(ParseError::EmptySpectrum);
+  }
+
+  metadata.instrument = state.instrument.describe();
+
+  Ok(Run {
+    metadata,
+    scans,
+  })
+}
+
+// Reads the arrays of spectra listed by list_spectra
+pub fn load_points(
+  s:        &String,
+  scans:    Vec<&mut Scan>,
+  progress: &Progress
+) -> Result<(), ParseError> {
+
+  // the arrays may refer to param groups defined in the header
+  let mut metadata = file_metadata(s);
+  let mut state = read_header(s, &mut metadata, progress)?;
+  state.single = true;
+
+  for scan in scans {
+
+    let offset = match scan.offset {
+      None         => { continue; }
+      Some(offset) => offset
+    };
+
+    let mut reader = spectrum_at(s, offset, &mut state, &mut metadata, progress)?;
+    let mut read: Vec<Scan> = vec![];
+
+    read_events(&mut reader, &mut state, &mut metadata, &mut read, None)?;
+
+    if let Some(spectrum) = read.pop() {
+      scan.points = spectrum.points;
+    }
+  }
+
+  Ok(())
+}
+
+// a reader positioned inside the <spectrum> starting at offset, whose
+// opening tag has already been handled
+fn spectrum_at(
+  s:        &String,
+  offset:   u64,
+  state:    &mut State,
+  md:       &mut Metadata,
+  progress: &Progress
+) -> Result<XmlReader, ParseError> {
+
+  let mut reader = reader_at(s, offset, progress)?;
+  let mut buf = Vec::new();
+
+  state.stack.clear();
+  state.scan = None;
+
+  match reader.read_event(&mut buf) {
+    Ok(Event::Start(ref tag)) if tag.name() == b"spectrum" => {
+      start_element(tag, &reader, state, md)?;
+      state.stack.push(tag.name().to_vec());
+      Ok(reader)
+    }
+    _ => Err(ParseError::Format(format!(
+      "index offset {} does not point at a <spectrum>", offset
+    )))
+  }
+}
+
+// everything up to the first spectrum: run-level metadata, the instrument
+// and the referenceable param groups
+fn read_header( s: &String, md: &mut Metadata, progress: &Progress ) -> Result<State, ParseError> {
+
+  let mut reader = reader_at(s, 0, progress)?;
+  let mut state  = State::default();
+
+  read_events(&mut reader, &mut state, md, &mut vec![], Some(b"spectrum"))?;
+
+  Ok(state)
+}
+
+// Feeds the events of reader to the state until the document ends or an
+// element named stop_at is about to open. Finished spectra are added to scans.
+fn read_events(
+  reader:  &mut XmlReader,
+  state:   &mut State,
+  md:      &mut Metadata,
+  scans:   &mut Vec<Scan>,
+  stop_at: Option<&[u8]>
+) -> Result<(), ParseError> {
+
+  let mut buf = Vec::new();
+
+  loop {
+
+    buf.clear();
+    match reader.read_event(&mut buf) {
+
+      Ok(Event::Start(ref tag)) |
+      Ok(Event::Empty(ref tag)) if Some(tag.name()) == stop_at => {
+        return Ok(());
+      }
+
+      Ok(Event::Start(ref tag)) => {
+        start_element(tag, reader, state, md)?;
+        state.stack.push(tag.name().to_vec());
+      }
+
+      Ok(Event::Empty(ref tag)) => {
+        start_element(tag, reader, state, md)?;
+        // <binary/> holds an empty array
+        state.in_binary = false;
+      }
+
+      Ok(Event::Text(ref txt)) if state.in_binary => {
+        state.content.extend_from_slice(txt);
+      }
+
+      Ok(Event::End(ref tag)) => {
+        state.stack.pop();
+
+        match tag.name() {
+          b"binary" => {
+            state.in_binary = false;
+          }
+          b"binaryDataArray" if state.scan.is_some() => {
+            end_array(state)?;
+          }
+          b"spectrum" => {
+            if let Some(mut scan) = state.scan.take() {
+              // NaN and inf decode like any other value, but cannot be
+              // sorted or plotted
+              scan.points =
+                state.mzs.iter().zip(state.intensities.iter())
+                  .filter(|(mz, int)| { mz.is_finite() && int.is_finite() })
+                  .map(|(mz, int)| { MSPoint { mz: *mz, int: *int, snr: 0.0 } })
+                  .collect();
+              scans.push(scan);
+
+              if state.single {
+                return Ok(());
+              }
+            }
+          }
+          _ => {}
+        }
+      }
+
+      Ok(Event::Eof) => { return Ok(()); }
+
+      Err(why) => {
+        return Err(ParseError::Xml(format!(
+          "{} at position {}",
+          why, reader.buffer_position()
+        )));
+      }
+
+      _ => {}
+    }
+  }
+}
+
+// handles opening and empty elements alike, since most mzML elements that
+// matter here can be written either way
+fn start_element(
+  tag:    &BytesStart,
+  reader: &XmlReader,
+  state:  &mut State,
+  md:     &mut Metadata
+) -> Result<(), ParseError> {
+
+  match tag.name() {
+
+    // userParams carry free text, e.g. instrument names outside the vocabulary
+    b"cvParam" | b"userParam" => {
+      let param  = CvParam::from_tag(tag, reader)?;
+      let parent = state.stack.last().cloned().unwrap_or_default();
+      cv_param(&param, &parent, state, md);
+    }
+
+    b"referenceableParamGroup" => {
+      let id = get_attribute(tag, reader, b"id")?;
+      state.param_groups.push((id, vec![]));
+    }
+
+    // applies the referenced group's terms as if they were written here
+    b"referenceableParamGroupRef" => {
+      let id     = get_attribute(tag, reader, b"ref")?;
+      let parent = state.stack.last().cloned().unwrap_or_default();
+      let params = state.param_groups.iter()
+        .find(|(group, _)| { *group == id })
+        .map(|(_, params)| { params.clone() })
+        .unwrap_or_default();
+
+      for param in params {
+        cv_param(&param, &parent, state, md);
+      }
+    }
+
+    b"sourceFile" => {
+      let location = get_attribute(tag, reader, b"location")?;
+      let name     = get_attribute(tag, reader, b"name")?;
+      md.source = if location.is_empty() {
+        name
+      } else {
+        format!("{}/{}", location.trim_end_matches('/'), name)
+      };
+    }
+
+    // the software's name is given by a cvParam inside the element
+    b"software" => {
+      state.software_version = get_attribute(tag, reader, b"version")?;
+    }
+
+    b"run" => {
+      // xs:dateTime, where the time zone is optional
+      let stamp = get_attribute(tag, reader, b"startTimeStamp")?;
+      if let Ok(date) = DateTime::parse_from_rfc3339(&stamp) {
+        md.date = date.with_timezone(&Utc);
+      } else if let Ok(date) = NaiveDateTime::parse_from_str(&stamp, "%Y-%m-%dT%H:%M:%S%.f") {
+        md.date = Utc.from_utc_datetime(&date);
+      }
+    }
+
+    b"spectrum" => {
+      let index = get_attribute(tag, reader, b"index")?;
+      let id    = get_attribute(tag, reader, b"id")?;
+
+      // ids look like "controllerType=0 controllerNumber=1 scan=42"
+      let num = id.split_whitespace()
+        .find_map(|part| { part.strip_prefix("scan=") })
+        .and_then(|n| { n.parse().ok() })
+        .unwrap_or(index.parse::<u32>().unwrap_or(0) + 1);
+
+      state.scan = Some(Scan {
+        num,
+        ms_level: 1,
+        ..Scan::default()
+      });
+      state.mzs.clear();
+      state.intensities.clear();
+    }
+
+    b"binaryDataArray" => {
+      state.encoding = ArrayEncoding::default();
+    }
+
+    b"binary" => {
+      state.content.clear();
+      state.in_binary = true;
+    }
+
+    _ => {}
+  }
+
+  Ok(())
+}
+
+// stores a term depending on the element it belongs to
+fn cv_param(
+  param:  &CvParam,
+  parent: &[u8],
+  state:  &mut State,
+  md:     &mut Metadata
+) {
+
+  match parent {
+
+    b"referenceableParamGroup" => {
+      if let Some((_, params)) = state.param_groups.last_mut() {
+        params.push(param.clone());
+      }
+    }
+
+    b"contact" => {
+      match param.accession.as_str() {
+        "MS:1000586" => { md.operator    = param.value.clone(); }
+        "MS:1000590" => { md.institution = param.value.clone(); }
+        "MS:1000587" |
+        "MS:1000588" |
+        "MS:1000589" => {
+          if !md.contact.is_empty() {
+            md.contact.push_str(", ");
+          }
+          md.contact.push_str(&param.value);
+        }
+        _ => {}
+      }
+    }
+
+    // instrument model, unless it is e.g. the serial number
+    b"instrumentConfiguration" if param.value.is_empty() => {
+      if !state.instrument.model.is_empty() {
+        state.instrument.model.push(' ');
+      }
+      state.instrument.model.push_str(&param.name);
+    }
+    b"source"   => { state.instrument.ionisation = param.name.clone(); }
+    b"analyzer" => { state.instrument.analyzer   = param.name.clone(); }
+    b"detector" => { state.instrument.detector   = param.name.clone(); }
+
+    b"software" => {
+      md.processing.push(
+        format!("{} {}", param.name, state.software_version).trim().to_string()
+      );
+    }
+    b"processingMethod" => {
+      md.processing.push(param.name.clone());
+    }
+
+    b"spectrum" | b"scan" => {
+      if let Some(scan) = state.scan.as_mut() {
+        match param.accession.as_str() {
+          "MS:1000511" => { scan.ms_level     = param.value.parse().unwrap_or(1); }
+          "MS:1000130" => { scan.polarity     = "+".to_string(); }
+          "MS:1000129" => { scan.polarity     = "-".to_string(); }
+          "MS:1000504" => { scan.base_peak_mz = param.value.parse().ok(); }
+          "MS:1000285" => { scan.tot_current  = param.value.parse().ok(); }
+          "MS:1000016" => {
+            // scan start time, usually in minutes
+            let factor = if param.unit == "UO:0000031" { 60.0 } else { 1.0 };
+            scan.retention_time = param.value.parse::<f64>().ok().map(|t| { t * factor });
+          }
+          _ => {}
+        }
+      }
+    }
+
+    b"selectedIon" => {
+      if let Some(scan) = state.scan.as_mut() {
+        match param.accession.as_str() {
+          "MS:1000744" => { scan.precursor_mz = param.value.parse().ok(); }
+          "MS:1000041" => { scan.charge       = param.value.parse().ok(); }
+          _ => {}
+        }
+      }
+    }
+
+    b"binaryDataArray" => {
+      let enc = &mut state.encoding;
+      match param.accession.as_str() {
+        "MS:1000521" => { enc.width      = 4; }
+        "MS:1000523" => { enc.width      = 8; }
+        "MS:1000574" => { enc.compressed = true; }
+        "MS:1000576" => { enc.compressed = false; }
+        "MS:1000514" => { enc.kind       = ArrayKind::Mz; }
+        "MS:1000515" => { enc.kind       = ArrayKind::Intensity; }
+        // integer arrays and MS-Numpress
+        "MS:1000519" | "MS:1000522" |
+        "MS:1002312" | "MS:1002313" | "MS:1002314" |
+        "MS:1002746" | "MS:1002747" | "MS:1002748" => {
+          enc.unsupported = Some(param.name.clone());
+        }
+        _ => {}
+      }
+    }
+
+    _ => {}
+  }
+}
+
+fn end_array( state: &mut State ) -> Result<(), ParseError> {
+
+  let enc = &state.encoding;
+
+  if enc.kind == ArrayKind::Other {
+    return Ok(());
+  }
+
+  if let Some(what) = &enc.unsupported {
+    return Err(ParseError::UnsupportedEncoding(what.clone()));
+  }
+
+  let mut bytes = decode_base64(&state.content)?;
+
+  if enc.compressed {
+    bytes = decompress(&bytes, 0)?;
+  }
+
+  let values = read_floats::<LittleEndian>(&bytes, enc.width);
+
+  if enc.kind == ArrayKind::Mz {
+    state.mzs = values;
+  } else {
+    state.intensities = values;
+  }
+
+  Ok(())
+}
+
+fn get_attribute(
+  tag:    &BytesStart,
+  reader: &XmlReader,
+  name:   &[u8]
+) -> Result<String, ParseError> {
+
+  Ok(
+    read_attributes(tag, reader)?.into_iter()
+      .find(|(key, _)| { key.as_slice() == name })
+      .map(|(_, value)| { value })
+      .unwrap_or_default()
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{ load_scans, parse_file, tests::temp_file };
+
+  use byteorder::WriteBytesExt;
+
+  // an array as the base64 of little-endian doubles
+  fn encode( values: &[f64] ) -> String {
+    let mut bytes = vec![];
+    for v in values {
+      bytes.write_f64::<LittleEndian>(*v).unwrap();
+    }
+    base64::encode(bytes)
+  }
+
+  fn array( accession: &str, binary: &str ) -> String {
+    format!(
+      "<binaryDataArray encodedLength=\"{}\">\n\
+       <cvParam cvRef=\"MS\" accession=\"MS:1000523\" name=\"64-bit float\"/>\n\
+       <cvParam cvRef=\"MS\" accession=\"MS:1000576\" name=\"no compression\"/>\n\
+       <cvParam cvRef=\"MS\" accession=\"{}\" name=\"array\"/>\n\
+       <binary>{}</binary>\n\
+       </binaryDataArray>\n",
+      binary.len(), accession, binary
+    )
+  }
+
+  // a file with a single spectrum holding the given m/z and intensity
+  // arrays, already encoded
+  fn read( name: &str, mzs: &str, ints: &str ) -> Result<Run, ParseError> {
+    let s = temp_file(name, format!(
+      "<?xml version=\"1.0\"?>\n<mzML><run id=\"r\" startTimeStamp=\"2021-03-04T05:06:07Z\">\n\
+       <spectrumList count=\"1\">\n\
+       <spectrum index=\"0\" id=\"controllerType=0 controllerNumber=1 scan=42\" defaultArrayLength=\"3\">\n\
+       <cvParam cvRef=\"MS\" accession=\"MS:1000511\" name=\"ms level\" value=\"2\"/>\n\
+       <scanList count=\"1\"><scan>\n\
+       <cvParam cvRef=\"MS\" accession=\"MS:1000016\" name=\"scan start time\" value=\"1.5\" unitAccession=\"UO:0000031\"/>\n\
+       </scan></scanList>\n\
+       <binaryDataArrayList count=\"2\">\n{}{}</binaryDataArrayList>\n\
+       </spectrum></spectrumList></run></mzML>\n",
+      array("MS:1000514", mzs), array("MS:1000515", ints)
+    ).as_bytes());
+    let run = parse_file(&s, &Progress::default());
+    std::fs::remove_file(&s).unwrap();
+    run
+  }
+
+  #[test]
+  fn reads_spectrum() {
+    let run = read(
+      "spectrum.mzML", &encode(&[100.0, 100.5, 101.0]), &encode(&[1.0, 20.0, 3.0])
+    ).unwrap();
+
+    let scan = &run.scans[0];
+    assert_eq!(scan.num, 42);
+    assert_eq!(scan.ms_level, 2);
+    assert_eq!(scan.retention_time, Some(90.0));
+
+    let points: Vec<(f64, f64)> = scan.points.iter().map(|pt| { (pt.mz, pt.int) }).collect();
+    assert_eq!(points, vec![(100.0, 1.0), (100.5, 20.0), (101.0, 3.0)]);
+  }
+
+  #[test]
+  fn reads_wrapped_arrays() {
+    let wrap = |text: String| -> String {
+      let lines: Vec<&str> = text.as_bytes().chunks(12)
+        .map(|line| { std::str::from_utf8(line).unwrap() })
+        .collect();
+      format!("\n  {}\n", lines.join("\n  "))
+    };
+    let run = read(
+      "wrapped.mzML", &wrap(encode(&[100.0, 100.5, 101.0])), &wrap(encode(&[1.0, 20.0, 3.0]))
+    ).unwrap();
+
+    assert_eq!(run.scans[0].points.len(), 3);
+    assert_eq!(run.scans[0].points[2].mz, 101.0);
+  }
+
+  #[test]
+  fn drops_non_finite_points() {
+    let run = read(
+      "nan.mzML",
+      &encode(&[100.0, f64::NEG_INFINITY, 101.0, 102.0]),
+      &encode(&[1.0, 2.0, f64::NAN, 4.0])
+    ).unwrap();
+
+    let mzs: Vec<f64> = run.scans[0].points.iter().map(|pt| { pt.mz }).collect();
+    assert_eq!(mzs, vec![100.0, 102.0]);
+  }
+
+  // only the attributes of indexed spectra are read until load_scans is called
+  #[test]
+  fn lists_indexed_spectra() {
+    let mut xml = String::from(
+      "<?xml version=\"1.0\"?>\n<indexedmzML><mzML><run id=\"r\">\n<spectrumList count=\"2\">\n"
+    );
+    let mut offsets = vec![];
+    for (i, mz) in [(0, 100.0), (1, 200.0)] {
+      offsets.push(xml.len());
+      xml += &format!(
+        "<spectrum index=\"{}\" id=\"scan={}\" defaultArrayLength=\"1\">\n\
+         <cvParam cvRef=\"MS\" accession=\"MS:1000511\" name=\"ms level\" value=\"1\"/>\n\
+         <binaryDataArrayList count=\"2\">\n{}{}</binaryDataArrayList>\n\
+         </spectrum>\n",
+        i, i + 1, array("MS:1000514", &encode(&[mz])), array("MS:1000515", &encode(&[5.0]))
+      );
+    }
+    xml += "</spectrumList></run></mzML>\n";
+    let index_offset = xml.len();
+    xml += "<indexList count=\"1\"><index name=\"spectrum\">\n";
+    for (i, offset) in offsets.iter().enumerate() {
+      xml += &format!("<offset idRef=\"scan={}\">{}</offset>\n", i + 1, offset);
+    }
+    xml += &format!(
+      "</index></indexList>\n<indexListOffset>{}</indexListOffset>\n</indexedmzML>\n", index_offset
+    );
+
+    let s = temp_file("indexed.mzML", xml.as_bytes());
+    let mut run = parse_file(&s, &Progress::default()).unwrap();
+
+    assert_eq!(run.scans.len(), 2);
+    assert_eq!(run.scans[1].num, 2);
+    assert_eq!(run.scans[1].offset, Some(offsets[1] as u64));
+    assert!(run.scans.iter().all(|scan| { scan.points.is_empty() }));
+
+    let loaded = load_scans(&mut run, 2, 2, &Progress::default());
+    std::fs::remove_file(&s).unwrap();
+    loaded.unwrap();
+
+    assert!(run.scans[0].points.is_empty());
+    assert_eq!(run.scans[1].points.len(), 1);
+    assert_eq!(run.scans[1].points[0].mz, 200.0);
+  }
+}
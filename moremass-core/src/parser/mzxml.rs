@@ -0,0 +1,631 @@
+
+use crate::{ Metadata, MSPoint, Run, Scan };
+use crate::parser::{
+  ParseError, InstrumentInfo,
+  read_attributes, decode_base64, decompress, read_floats, file_metadata,
+  XmlReader, Progress, read_index, reader_at,
+};
+
+use byteorder::{ BigEndian, LittleEndian };
+use quick_xml::events::{ Event, BytesStart };
+
+// Describes how the contents of a <peaks> element are encoded.
+// Defaults are the ones given by the mzXML schema.
+#[derive(Debug, Clone)]
+struct PeaksEncoding {
+  precision:      String,
+  byte_order:     String,
+  compression:    String,
+  compressed_len: usize,
+  content_type:   String,
+}
+
+impl Default for PeaksEncoding {
+  fn default() -> Self {
+    PeaksEncoding {
+      precision:      "32".to_string(),
+      byte_order:     "network".to_string(),
+      compression:    "none".to_string(),
+      compressed_len: 0,
+      content_type:   "m/z-int".to_string(),
+    }
+  }
+}
+
+impl PeaksEncoding {
+  fn from_tag( tag: &BytesStart, reader: &XmlReader ) -> Result<Self, ParseError> {
+
+    let mut enc = PeaksEncoding::default();
+
+    for (key, value) in read_attributes(tag, reader)? {
+      match key.as_slice() {
+        b"precision"       => { enc.precision      = value; }
+        b"byteOrder"       => { enc.byte_order     = value; }
+        b"compressionType" => { enc.compression    = value; }
+        b"compressedLen"   => { enc.compressed_len = value.parse().unwrap_or(0); }
+        // older versions of the schema call it pairOrder
+        b"contentType" |
+        b"pairOrder"       => { enc.content_type   = value; }
+        _ => {}
+      }
+    }
+
+    Ok(enc)
+  }
+
+  fn is_supported(&self) -> bool {
+    (self.precision == "32" || self.precision == "64") &&
+    self.is_little_endian().is_some() &&
+    (self.compression == "none" || self.compression == "zlib") &&
+    self.content_type == "m/z-int"
+  }
+
+  // the schema only allows "network", but some tools write their native order
+  fn is_little_endian(&self) -> Option<bool> {
+    match self.byte_order.as_str() {
+      "network" | "big" => Some(false),
+      "little"          => Some(true),
+      _                 => None
+    }
+  }
+}
+
+pub fn parse_mzxml( s: &String, progress: &Progress ) -> Result<Run, ParseError> {
+
+  // indexed files let us list the scans without decoding their peaks. If
+  // the index turns out to be wrong, the whole file is read after all.
+  if let Some(offsets) = read_index(s, "indexOffset", "scan", progress)? {
+    if let Ok(run) = list_scans(s, &offsets, progress) {
+      return Ok(run);
+    }
+  }
+
+  let mut reader = reader_at(s, 0, progress)?;
+
+  let mut buf = Vec::new();
+
+  // scans can be nested (MS/MS scans inside their precursor scan), so the
+  // currently open ones are kept on a stack
+  let mut open_scans: Vec<Scan> = vec![];
+  let mut scans:      Vec<Scan> = vec![];
+
+  let mut metadata   = file_metadata(s);
+  let mut instrument = InstrumentInfo::default();
+
+  // the encoding of the <peaks> element we are currently in, if any
+  let mut encoding: Option<PeaksEncoding> = None;
+  let mut content = Vec::new();
+
+  // whether we are inside a <precursorMz> element
+  let mut in_precursor = false;
+
+  loop {
+
+    buf.clear();
+    match reader.read_event(&mut buf) {
+
+      Ok(Event::Start(ref tag)) if tag.name() == b"scan" => {
+        open_scans.push(scan_from_tag(tag, &reader)?);
+      }
+
+      Ok(Event::End(ref tag)) if tag.name() == b"scan" => {
+        match open_scans.pop() {
+          None => {
+            return Err(ParseError::Xml(format!(
+              "</scan> without matching <scan> at position {}",
+              reader.buffer_position()
+            )));
+          }
+          Some(scan) => { scans.push(scan); }
+        }
+      }
+
+      Ok(Event::Start(ref tag)) if tag.name() == b"peaks" => {
+        encoding = Some(PeaksEncoding::from_tag(tag, &reader)?);
+        content.clear();
+      }
+
+      Ok(Event::Text(ref txt)) if encoding.is_some() => {
+        content.extend_from_slice(txt);
+      }
+
+      Ok(Event::Start(ref tag)) if tag.name() == b"precursorMz" => {
+        in_precursor = true;
+        if let Some(scan) = open_scans.last_mut() {
+          precursor_from_tag(tag, &reader, scan)?;
+        }
+      }
+
+      Ok(Event::Text(ref txt)) if in_precursor => {
+        if let Some(scan) = open_scans.last_mut() {
+          scan.precursor_mz = String::from_utf8_lossy(txt).trim().parse().ok();
+        }
+      }
+
+      Ok(Event::End(ref tag)) if tag.name() == b"precursorMz" => {
+        in_precursor = false;
+      }
+
+      Ok(Event::End(ref tag)) if tag.name() == b"peaks" => {
+
+        let enc = match encoding.take() {
+          None => {
+            return Err(ParseError::Xml(format!(
+              "</peaks> without matching <peaks> at position {}",
+              reader.buffer_position()
+            )));
+          }
+          Some(e) => e
+        };
+
+        // empty scans carry no peaks
+        if content.is_empty() {
+          continue;
+        }
+
+        let points = decode_peaks(&content, &enc)?;
+
+        match open_scans.last_mut() {
+          None => {
+            return Err(ParseError::Xml(format!(
+              "<peaks> outside of a <scan> at position {}",
+              reader.buffer_position()
+            )));
+          }
+          Some(scan) => {
+            scan.points = points;
+          }
+        }
+      }
+
+      Ok(Event::Start(ref tag)) |
+      Ok(Event::Empty(ref tag)) => {
+        read_metadata(tag, &reader, &mut metadata, &mut instrument)?;
+      }
+
+      Ok(Event::Eof) => { break; }
+
+      Err(why) => {
+        return Err(ParseError::Xml(format!(
+          "{} at position {}",
+          why, reader.buffer_position()
+        )));
+      }
+
+      _ => {}
+    }
+  }
+
+  // nested scans were closed before their parents
+  scans.sort_by_key(|scan| scan.num);
+  scans.retain(|scan| !scan.points.is_empty());
+
+  if scans.is_empty() {
+    return Err(ParseError::EmptySpectrum);
+  }
+
+  metadata.instrument = instrument.describe();
+
+  Ok(Run {
+    metadata,
+    scans,
+  })
+}
+
+// Lists the scans found at the given offsets, reading only their attributes
+// and precursor. The peaks are read by load_points once they are needed.
+fn list_scans( s: &String, offsets: &Vec<u64>, progress: &Progress ) -> Result<Run, ParseError> {
+
+  let mut reader = reader_at(s, 0, progress)?;
+  let mut buf = Vec::new();
+
+  let mut metadata   = file_metadata(s);
+  let mut instrument = InstrumentInfo::default();
+
+  // the run-level information comes before the first scan
+  loop {
+
+    buf.clear();
+    match reader.read_event(&mut buf) {
+      Ok(Event::Start(ref tag)) if tag.name() == b"scan" => { break; }
+      Ok(Event::Start(ref tag)) |
+      Ok(Event::Empty(ref tag)) => {
+        read_metadata(tag, &reader, &mut metadata, &mut instrument)?;
+      }
+      Ok(Event::Eof) => { break; }
+      Err(why) => {
+        return Err(ParseError::Xml(format!(
+          "{} at position {}",
+          why, reader.buffer_position()
+        )));
+      }
+      _ => {}
+    }
+  }
+
+  let mut scans: Vec<Scan> = vec![];
+
+  for offset in offsets {
+
+    let mut reader = reader_at(s, *offset, progress)?;
+
+    buf.clear();
+    let mut scan = match reader.read_event(&mut buf) {
+      Ok(Event::Start(ref tag)) if tag.name() == b"scan" => {
+        scan_from_tag(tag, &reader)?
+      }
+      _ => {
+        return Err(ParseError::Format(format!(
+          "index offset {} does not point at a <scan>", offset
+        )));
+      }
+    };
+    scan.offset = Some(*offset);
+
+    // stop at the peaks, which always come after the precursor
+    let mut in_precursor = false;
+    loop {
+
+      buf.clear();
+      match reader.read_event(&mut buf) {
+        Ok(Event::Start(ref tag)) if tag.name() == b"precursorMz" => {
+          in_precursor = true;
+          precursor_from_tag(tag, &reader, &mut scan)?;
+        }
+        Ok(Event::Text(ref txt)) if in_precursor => {
+          scan.precursor_mz = String::from_utf8_lossy(txt).trim().parse().ok();
+        }
+        Ok(Event::End(ref tag)) if tag.name() == b"precursorMz" => {
+          in_precursor = false;
+        }
+        Ok(Event::Start(ref tag)) |
+        Ok(Event::Empty(ref tag)) if tag.name() == b"peaks" || tag.name() == b"scan" => {
+          break;
+        }
+        Ok(Event::End(ref tag)) if tag.name() == b"scan" => { break; }
+        Ok(Event::Eof) => { break; }
+        Err(why) => {
+          return Err(ParseError::Xml(format!(
+            "{} at position {}",
+            why, offset + reader.buffer_position() as u64
+          )));
+        }
+        _ => {}
+      }
+    }
+
+    scans.push(scan);
+  }
+
+  if scans.is_empty() {
+    return Err(ParseError::EmptySpectrum);
+  }
+
+  scans.sort_by_key(|scan| scan.num);
+  metadata.instrument = instrument.describe();
+
+  Ok(Run {
+    metadata,
+    scans,
+  })
+}
+
+// Reads the peaks of scans listed by list_scans
+pub fn load_points(
+  s:        &String,
+  scans:    Vec<&mut Scan>,
+  progress: &Progress
+) -> Result<(), ParseError> {
+
+  let mut buf = Vec::new();
+
+  for scan in scans {
+
+    let offset = match scan.offset {
+      None         => { continue; }
+      Some(offset) => offset
+    };
+    let mut reader = reader_at(s, offset, progress)?;
+
+    let mut depth = 0;
+    let mut encoding: Option<PeaksEncoding> = None;
+    let mut content = Vec::new();
+
+    loop {
+
+      buf.clear();
+      match reader.read_event(&mut buf) {
+        Ok(Event::Start(ref tag)) if tag.name() == b"scan" => {
+          // a nested scan means this one had no peaks
+          depth += 1;
+          if depth > 1 { break; }
+        }
+        Ok(Event::Start(ref tag)) if tag.name() == b"peaks" => {
+          encoding = Some(PeaksEncoding::from_tag(tag, &reader)?);
+        }
+        Ok(Event::Text(ref txt)) if encoding.is_some() => {
+          content.extend_from_slice(txt);
+        }
+        Ok(Event::End(ref tag)) if tag.name() == b"peaks" => {
+          if let Some(enc) = encoding.take() {
+            if !content.is_empty() {
+              scan.points = decode_peaks(&content, &enc)?;
+            }
+          }
+          break;
+        }
+        Ok(Event::Empty(ref tag)) if tag.name() == b"peaks" => { break; }
+        Ok(Event::End(ref tag))   if tag.name() == b"scan"  => { break; }
+        Ok(Event::Eof) => { break; }
+        Err(why) => {
+          return Err(ParseError::Xml(format!(
+            "{} at position {}",
+            why, offset + reader.buffer_position() as u64
+          )));
+        }
+        _ => {}
+      }
+    }
+  }
+
+  Ok(())
+}
+
+// Picks up the run-level information of mzXML 3.x (<msInstrument>) and
+// 2.x (<instrument>) files
+fn read_metadata(
+  tag:        &BytesStart,
+  reader:     &XmlReader,
+  md:         &mut Metadata,
+  instrument: &mut InstrumentInfo
+) -> Result<(), ParseError> {
+
+  let attributes = read_attributes(tag, reader)?;
+  let get = |name: &[u8]| -> String {
+    attributes.iter()
+      .find(|(key, _)| { key.as_slice() == name })
+      .map(|(_, value)| { value.clone() })
+      .unwrap_or_default()
+  };
+
+  match tag.name() {
+    b"parentFile" => {
+      md.source = get(b"fileName");
+    }
+    b"msManufacturer" | b"manufacturer" => {
+      instrument.manufacturer = get(b"value");
+    }
+    b"msModel" | b"model" => {
+      instrument.model = get(b"value");
+    }
+    b"msIonisation" | b"ionisation" => {
+      instrument.ionisation = get(b"value");
+    }
+    b"msMassAnalyzer" | b"mzAnalyzer" | b"analyzer" => {
+      instrument.analyzer = get(b"value");
+    }
+    b"msDetector" | b"detector" => {
+      instrument.detector = get(b"value");
+    }
+    b"operator" => {
+      md.operator = format!("{} {}", get(b"first"), get(b"last")).trim().to_string();
+      md.contact  = [get(b"email"), get(b"phone"), get(b"URI")].iter()
+        .filter(|s| { !s.is_empty() })
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(", ");
+    }
+    b"software" => {
+      md.processing.push(
+        format!("{} {} ({})", get(b"name"), get(b"version"), get(b"type"))
+      );
+    }
+    b"processingOperation" => {
+      md.processing.push(get(b"name"));
+    }
+    _ => {}
+  }
+
+  Ok(())
+}
+
+// reads the attributes of a <scan> element; its peaks are filled in later
+fn scan_from_tag( tag: &BytesStart, reader: &XmlReader ) -> Result<Scan, ParseError> {
+
+  let mut scan = Scan::default();
+
+  for (key, value) in read_attributes(tag, reader)? {
+    match key.as_slice() {
+      b"num"            => { scan.num            = value.parse().unwrap_or(0); }
+      b"msLevel"        => { scan.ms_level       = value.parse().unwrap_or(1); }
+      b"retentionTime"  => { scan.retention_time = parse_duration(&value); }
+      b"polarity"       => { scan.polarity       = value; }
+      b"basePeakMz"     => { scan.base_peak_mz   = value.parse().ok(); }
+      b"totIonCurrent"  => { scan.tot_current    = value.parse().ok(); }
+      _ => {}
+    }
+  }
+
+  Ok(scan)
+}
+
+// the precursor's m/z is the element's text, its charge an attribute
+fn precursor_from_tag(
+  tag:    &BytesStart,
+  reader: &XmlReader,
+  scan:   &mut Scan
+) -> Result<(), ParseError> {
+
+  for (key, value) in read_attributes(tag, reader)? {
+    if key.as_slice() == b"precursorCharge" {
+      scan.charge = value.parse().ok();
+    }
+  }
+
+  Ok(())
+}
+
+// parses the xs:duration values mzXML uses for times, e.g. "PT1M3.5S",
+// into seconds
+fn parse_duration( s: &str ) -> Option<f64> {
+
+  let mut rest = s.strip_prefix("PT")?;
+  let mut secs = 0.0;
+
+  while !rest.is_empty() {
+    let end = rest.find(|c: char| c.is_alphabetic())?;
+    let value: f64 = rest[..end].parse().ok()?;
+
+    secs += value * match &rest[end..end+1] {
+      "H" => 3600.0,
+      "M" => 60.0,
+      "S" => 1.0,
+      _   => { return None; }
+    };
+    rest = &rest[end+1..];
+  }
+
+  Some(secs)
+}
+
+// decodes the base64 text of a <peaks> element
+fn decode_peaks( content: &[u8], enc: &PeaksEncoding ) -> Result<Vec<MSPoint>, ParseError> {
+
+  if !enc.is_supported() {
+    return Err(ParseError::UnsupportedEncoding(format!(
+      "precision {}, byte order {}, compression {}, content type {}",
+      enc.precision, enc.byte_order, enc.compression, enc.content_type
+    )));
+  }
+
+  let mut bytes = decode_base64(content)?;
+
+  if enc.compression == "zlib" {
+    bytes = decompress(&bytes, enc.compressed_len)?;
+  }
+
+  Ok(decode_points(&bytes, enc))
+}
+
+// reads (m/z, intensity) pairs in the precision and byte order given by enc
+fn decode_points( bytes: &Vec<u8>, enc: &PeaksEncoding ) -> Vec<MSPoint> {
+
+  let values = match (enc.precision.as_str(), enc.is_little_endian()) {
+    ("32", Some(true))  => read_floats::<LittleEndian>(bytes, 4),
+    ("32", Some(false)) => read_floats::<BigEndian>(bytes, 4),
+    ("64", Some(true))  => read_floats::<LittleEndian>(bytes, 8),
+    ("64", Some(false)) => read_floats::<BigEndian>(bytes, 8),
+    _ => vec![]
+  };
+
+  // NaN and inf decode like any other value, but cannot be sorted or plotted
+  values.chunks_exact(2)
+    .filter(|pair| { pair[0].is_finite() && pair[1].is_finite() })
+    .map(|pair| { MSPoint {mz: pair[0], int: pair[1], snr: 0.0} })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{ load_scans, parse_file, tests::temp_file };
+
+  use byteorder::WriteBytesExt;
+
+  // (m/z, intensity) pairs as the base64 of network order doubles
+  fn encode( pairs: &[(f64, f64)] ) -> String {
+    let mut bytes = vec![];
+    for (mz, int) in pairs {
+      bytes.write_f64::<BigEndian>(*mz).unwrap();
+      bytes.write_f64::<BigEndian>(*int).unwrap();
+    }
+    base64::encode(bytes)
+  }
+
+  // a file with a single scan holding the given peaks text
+  fn read( name: &str, peaks: &str ) -> Result<Run, ParseError> {
+    let s = temp_file(name, format!(
+      "<?xml version=\"1.0\"?>\n<mzXML><msRun scanCount=\"1\">\n\
+       <parentFile fileName=\"run.raw\" fileType=\"RAWData\" fileSha1=\"0\"/>\n\
+       <scan num=\"7\" msLevel=\"1\" peaksCount=\"3\" retentionTime=\"PT12.5S\">\n\
+       <peaks precision=\"64\" byteOrder=\"network\" contentType=\"m/z-int\">{}</peaks>\n\
+       </scan></msRun></mzXML>\n",
+      peaks
+    ).as_bytes());
+    let run = parse_file(&s, &Progress::default());
+    std::fs::remove_file(&s).unwrap();
+    run
+  }
+
+  #[test]
+  fn reads_scan() {
+    let run = read("scan.mzXML", &encode(&[(100.0, 1.0), (100.5, 20.0), (101.0, 3.0)])).unwrap();
+
+    assert_eq!(run.metadata.source, "run.raw");
+    assert_eq!(run.scans.len(), 1);
+    assert_eq!(run.scans[0].num, 7);
+    assert_eq!(run.scans[0].retention_time, Some(12.5));
+
+    let points: Vec<(f64, f64)> = run.scans[0].points.iter().map(|pt| { (pt.mz, pt.int) }).collect();
+    assert_eq!(points, vec![(100.0, 1.0), (100.5, 20.0), (101.0, 3.0)]);
+  }
+
+  #[test]
+  fn drops_non_finite_points() {
+    let pairs = [(100.0, 1.0), (100.5, f64::NAN), (f64::INFINITY, 2.0), (101.0, 3.0)];
+    let run = read("nan.mzXML", &encode(&pairs)).unwrap();
+
+    let mzs: Vec<f64> = run.scans[0].points.iter().map(|pt| { pt.mz }).collect();
+    assert_eq!(mzs, vec![100.0, 101.0]);
+  }
+
+  #[test]
+  fn reads_wrapped_peaks() {
+    let text  = encode(&[(100.0, 1.0), (100.5, 20.0), (101.0, 3.0)]);
+    let lines: Vec<String> = text.as_bytes().chunks(16)
+      .map(|line| { String::from_utf8(line.to_vec()).unwrap() })
+      .collect();
+    let run = read("wrapped.mzXML", &format!("\n{}\n", lines.join("\n    "))).unwrap();
+
+    assert_eq!(run.scans[0].points.len(), 3);
+    assert_eq!(run.scans[0].points[1].int, 20.0);
+  }
+
+  // only the attributes of indexed scans are read until load_scans is called
+  #[test]
+  fn lists_indexed_scans() {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<mzXML><msRun scanCount=\"2\">\n");
+    let mut offsets = vec![];
+    for (num, mz) in [(1, 100.0), (2, 200.0)] {
+      offsets.push(xml.len());
+      xml += &format!(
+        "<scan num=\"{}\" msLevel=\"1\" peaksCount=\"1\">\n\
+         <peaks precision=\"64\" byteOrder=\"network\" contentType=\"m/z-int\">{}</peaks>\n\
+         </scan>\n",
+        num, encode(&[(mz, 5.0)])
+      );
+    }
+    xml += "</msRun>\n";
+    let index_offset = xml.len();
+    xml += "<index name=\"scan\">\n";
+    for (i, offset) in offsets.iter().enumerate() {
+      xml += &format!("<offset id=\"{}\">{}</offset>\n", i + 1, offset);
+    }
+    xml += &format!("</index>\n<indexOffset>{}</indexOffset>\n</mzXML>\n", index_offset);
+
+    let s = temp_file("indexed.mzXML", xml.as_bytes());
+    let mut run = parse_file(&s, &Progress::default()).unwrap();
+
+    assert_eq!(run.scans.len(), 2);
+    assert_eq!(run.scans[1].offset, Some(offsets[1] as u64));
+    assert!(run.scans.iter().all(|scan| { scan.points.is_empty() }));
+
+    let loaded = load_scans(&mut run, 2, 2, &Progress::default());
+    std::fs::remove_file(&s).unwrap();
+    loaded.unwrap();
+
+    assert!(run.scans[0].points.is_empty());
+    assert_eq!(run.scans[1].points.len(), 1);
+    assert_eq!(run.scans[1].points[0].mz, 200.0);
+  }
+}
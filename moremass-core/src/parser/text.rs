@@ -0,0 +1,160 @@
+
+use crate::{ MSPoint, Run, Scan };
+use crate::parser::{ ParseError, Progress, file_metadata, open };
+
+use std::io::Read;
+
+// how many lines are looked at to guess the delimiter
+const SNIFF_LINES: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Delimiter {
+  Tab,
+  Semicolon,
+  Comma,
+  Whitespace,
+}
+
+const DELIMITERS: [Delimiter; 4] = [
+  Delimiter::Tab,
+  Delimiter::Semicolon,
+  Delimiter::Comma,
+  Delimiter::Whitespace,
+];
+
+// Reads two-column (m/z, intensity) text files as exported by most vendor
+// software. Lines that do not start with two numbers are skipped, so headers
+// and comments need no special treatment.
+pub fn parse_text( s: &String, progress: &Progress ) -> Result<Run, ParseError> {
+
+  let mut content = String::new();
+  open(s, progress)?.read_to_string(&mut content)?;
+
+  let lines: Vec<&str> =
+    content.lines()
+      .map(|line| { line.trim() })
+      .filter(|line| { !line.is_empty() && !is_comment(line) })
+      .collect();
+
+  // use whichever delimiter can read the most of the first lines
+  let delimiter = DELIMITERS.iter()
+    .map(|d| {
+      let hits = lines.iter()
+        .take(SNIFF_LINES)
+        .filter(|line| { parse_line(line, *d).is_some() })
+        .count();
+      (*d, hits)
+    })
+    .fold((Delimiter::Whitespace, 0), |best, candidate| {
+      if candidate.1 > best.1 { candidate } else { best }
+    }).0;
+
+  let mut points: Vec<MSPoint> =
+    lines.iter()
+      .filter_map(|line| { parse_line(line, delimiter) })
+      .map(|(mz, int)| { MSPoint { mz, int, snr: 0.0 } })
+      .collect();
+
+  if points.is_empty() {
+    return Err(ParseError::EmptySpectrum);
+  }
+
+  points.sort_by(|a, b| { a.mz.partial_cmp(&b.mz).unwrap() });
+
+  Ok(Run {
+    metadata: file_metadata(s),
+    scans: vec![
+      Scan {
+        num:      1,
+        ms_level: 1,
+        points,
+        ..Scan::default()
+      }
+    ],
+  })
+}
+
+fn is_comment( line: &str ) -> bool {
+  line.starts_with('#')  ||
+  line.starts_with('%')  ||
+  line.starts_with('!')  ||
+  line.starts_with("//")
+}
+
+// reads the first two fields of a line as numbers. Unless the fields are
+// separated by commas, a comma is taken to be a decimal separator.
+fn parse_line( line: &str, delimiter: Delimiter ) -> Option<(f64, f64)> {
+
+  let mut fields: Box<dyn Iterator<Item = &str>> = match delimiter {
+    Delimiter::Tab        => Box::new(line.split('\t')),
+    Delimiter::Semicolon  => Box::new(line.split(';')),
+    Delimiter::Comma      => Box::new(line.split(',')),
+    Delimiter::Whitespace => Box::new(line.split_whitespace()),
+  };
+
+  let mut number = || -> Option<f64> {
+    let field = fields.next()?.trim().trim_matches('"');
+    if delimiter == Delimiter::Comma {
+      field.parse().ok()
+    } else {
+      field.replace(',', ".").parse().ok()
+    }
+  };
+
+  let mz  = number()?;
+  let int = number()?;
+
+  if mz.is_finite() && int.is_finite() {
+    Some((mz, int))
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::{ parse_file, tests::temp_file };
+
+  fn read( name: &str, content: &str ) -> Vec<(f64, f64)> {
+    let s   = temp_file(name, content.as_bytes());
+    let run = parse_file(&s, &Progress::default());
+    std::fs::remove_file(&s).unwrap();
+    run.unwrap().scans[0].points.iter().map(|pt| { (pt.mz, pt.int) }).collect()
+  }
+
+  #[test]
+  fn splits_fields() {
+    assert_eq!(parse_line("100.5\t20", Delimiter::Tab),           Some((100.5, 20.0)));
+    assert_eq!(parse_line("100,5;20,25", Delimiter::Semicolon),   Some((100.5, 20.25)));
+    assert_eq!(parse_line("\"100.5\",\"20\"", Delimiter::Comma),  Some((100.5, 20.0)));
+    assert_eq!(parse_line("100.5   20  7", Delimiter::Whitespace), Some((100.5, 20.0)));
+    assert_eq!(parse_line("100.5,20", Delimiter::Whitespace),     None);
+    assert_eq!(parse_line("m/z\tintensity", Delimiter::Tab),      None);
+    assert_eq!(parse_line("100.5 inf", Delimiter::Whitespace),    None);
+  }
+
+  #[test]
+  fn guesses_delimiter() {
+    let expected = vec![(100.5, 1.0), (101.0, 2.5), (101.5, 3.0)];
+
+    assert_eq!(read("comma.csv", "m/z,intensity\n100.5,1\n101.0,2.5\n101.5,3\n"), expected);
+    assert_eq!(read("semicolon.csv", "# exported\n100,5;1\n101,0;2,5\n101,5;3\n"), expected);
+    assert_eq!(read("tab.txt", "100.5\t1\n101.0\t2.5\n101.5\t3\n"), expected);
+    assert_eq!(read("space.xy", "  100.5  1\n\n101.0 2.5\n101.5     3\n"), expected);
+  }
+
+  #[test]
+  fn sorts_points() {
+    assert_eq!(
+      read("unsorted.txt", "101.5 3\n100.5 1\n101.0 2.5\n"),
+      vec![(100.5, 1.0), (101.0, 2.5), (101.5, 3.0)]
+    );
+  }
+
+  // files with other extensions are recognised by their content
+  #[test]
+  fn reads_unknown_extension() {
+    assert_eq!(read("spectrum.out", "100.5\t1\n101.0\t2.5\n"), vec![(100.5, 1.0), (101.0, 2.5)]);
+  }
+}
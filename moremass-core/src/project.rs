@@ -0,0 +1,88 @@
+//! Saving and restoring a whole analysis as a .mmproj file.
+
+use crate::{ Data, parser::{ ParseError, Progress, open_whole } };
+
+use std::fs::File;
+use std::path::Path;
+use std::io::{ self, BufReader, BufWriter, Write };
+
+use serde::{ Serialize, Deserialize };
+
+/// The extension project files are recognised by
+pub const EXTENSION: &str = "mmproj";
+
+// bumped whenever the layout of the saved structures changes
+const VERSION: u32 = 2;
+
+/// The part of the plot state worth restoring
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct View {
+  pub x0: f32,
+  pub y0: f32,
+  pub sx: f32,
+  pub sy: f32,
+}
+
+/// Everything that is needed to pick up an analysis where it was left
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Project {
+  pub version: u32,
+  pub data:    Data,
+  pub view:    View,
+}
+
+/// Whether s names a project file
+pub fn is_project( s: &String ) -> bool {
+  Path::new(s).extension()
+    .map(|e| { e.to_string_lossy().to_lowercase() == EXTENSION })
+    .unwrap_or(false)
+}
+
+/// Saves the datasets along with the plot view
+pub fn save_project( data: &Data, view: View, s: &String ) -> io::Result<()> {
+
+  let mut out = BufWriter::new(File::create(s)?);
+
+  serde_json::to_writer(
+    &mut out,
+    &ProjectRef { version: VERSION, data, view }
+  )?;
+
+  out.flush()
+}
+
+/// Reads a project, refusing ones saved by newer versions. Reports to
+/// progress like parse_file.
+pub fn load_project( s: &String, progress: &Progress ) -> Result<Project, ParseError> {
+
+  let file = open_whole(s, progress)?;
+  let mut project: Project = match serde_json::from_reader(BufReader::new(file)) {
+    Err(_) if progress.is_cancelled() => { return Err(ParseError::Cancelled); }
+    Err(why) => { return Err(ParseError::Format(why.to_string())); }
+    Ok(p)    => p
+  };
+
+  if project.version > VERSION {
+    return Err(ParseError::Format(format!(
+      "project was saved by a newer version of MoreMass (format {})",
+      project.version
+    )));
+  }
+
+  // format 1 had neither fitted peaks nor local noise estimates
+  if project.version < 2 {
+    for ds in project.data.sets.iter_mut() {
+      ds.recompute();
+    }
+  }
+
+  Ok(project)
+}
+
+// same layout as Project, but without having to clone the data for saving
+#[derive(Serialize)]
+struct ProjectRef<'a> {
+  version: u32,
+  data:    &'a Data,
+  view:    View,
+}
@@ -0,0 +1,264 @@
+//! Smoothing noisy profile spectra, so that small wiggles do not turn into
+//! local maxima.
+
+use crate::{ MSPoint, calcs };
+
+/// The filters a spectrum can be smoothed with
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Method {
+  SavitzkyGolay,
+  Gaussian,
+  MovingAverage,
+}
+
+impl Method {
+  pub const ALL: [Method; 3] = [
+    Method::SavitzkyGolay,
+    Method::Gaussian,
+    Method::MovingAverage,
+  ];
+}
+
+impl std::fmt::Display for Method {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        Method::SavitzkyGolay => "Savitzky-Golay",
+        Method::Gaussian      => "Gaussian",
+        Method::MovingAverage => "Moving Average",
+      }
+    )
+  }
+}
+
+/// What the window width is measured in
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Unit {
+  Points,
+  Mz,
+}
+
+impl Unit {
+  pub const ALL: [Unit; 2] = [
+    Unit::Points,
+    Unit::Mz,
+  ];
+}
+
+impl std::fmt::Display for Unit {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        Unit::Points => "points",
+        Unit::Mz     => "m/z",
+      }
+    )
+  }
+}
+
+/// How to smooth a spectrum. A window given in m/z is converted to points
+/// using the median distance between neighbouring points. order is only used
+/// by Savitzky-Golay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Smoothing {
+  pub method: Method,
+  pub width:  f64,
+  pub unit:   Unit,
+  pub order:  usize,
+}
+
+impl Default for Smoothing {
+  fn default() -> Self {
+    Smoothing {
+      method: Method::SavitzkyGolay,
+      width:  7.0,
+      unit:   Unit::Points,
+      order:  2,
+    }
+  }
+}
+
+impl Smoothing {
+
+  /// The smoothed intensity of each of the points
+  pub fn apply(&self, pts: &[MSPoint]) -> Vec<f64> {
+    let ints: Vec<f64> = pts.iter().map(|pt| { pt.int }).collect();
+    let half = self.half_width(pts);
+    
+    if half == 0 {
+      return ints;
+    }
+    
+    convolve(&ints, &self.kernel(half))
+  }
+
+  /// How the smoothing is listed in the processing history
+  pub fn describe(&self) -> String {
+    match self.method {
+      Method::SavitzkyGolay => format!(
+        "Smoothing ({}, {} {}, order {})", self.method, self.width, self.unit, self.order
+      ),
+      _ => format!("Smoothing ({}, {} {})", self.method, self.width, self.unit),
+    }
+  }
+
+  // the window reaches this many points to either side. Never wider than
+  // the spectrum, so mirroring at the ends stays inside it.
+  fn half_width(&self, pts: &[MSPoint]) -> usize {
+    if pts.len() < 3 {
+      return 0;
+    }
+    
+    let points = match self.unit {
+      Unit::Points => self.width,
+      Unit::Mz => {
+        let mut spacing: Vec<f64> = pts.windows(2).map(|w| { w[1].mz - w[0].mz }).collect();
+        calcs::sort_in_place(&mut spacing);
+        let median = spacing[spacing.len() / 2];
+        if median > 0.0 { self.width / median } else { 0.0 }
+      }
+    };
+    
+    let half = ((points - 1.0) / 2.0).round().max(0.0) as usize;
+    half.min(pts.len() - 1)
+  }
+
+  // weights of the points from -half to +half, adding up to 1
+  fn kernel(&self, half: usize) -> Vec<f64> {
+    let offsets = (0..2*half+1).map(|k| { k as f64 - half as f64 });
+    
+    match self.method {
+      Method::MovingAverage => {
+        vec![1.0 / (2 * half + 1) as f64; 2 * half + 1]
+      }
+      
+      // the window covers three standard deviations to either side
+      Method::Gaussian => {
+        let sigma = half as f64 / 3.0;
+        let weights: Vec<f64> = offsets
+          .map(|x| { (-x * x / (2.0 * sigma * sigma)).exp() })
+          .collect();
+        let sum: f64 = weights.iter().sum();
+        weights.iter().map(|w| { w / sum }).collect()
+      }
+      
+      // The weight of a point is the value at the centre of the polynomial
+      // fitted to a spike at that point, as the fit is linear in the data
+      Method::SavitzkyGolay => {
+        let xs: Vec<f64> = offsets.collect();
+        let order = self.order.min(2 * half);
+        
+        (0..xs.len())
+          .map(|k| {
+            let mut spike = vec![0.0; xs.len()];
+            spike[k] = 1.0;
+            calcs::polyfit(&xs, &spike, order)
+              .map(|c| { c[0] })
+              .unwrap_or(1.0 / xs.len() as f64)
+          })
+          .collect()
+      }
+    }
+  }
+}
+
+// The ends of the spectrum are mirrored, so the window never runs out of
+// points
+fn convolve(ys: &[f64], kernel: &[f64]) -> Vec<f64> {
+  let n    = ys.len() as isize;
+  let half = (kernel.len() / 2) as isize;
+  
+  let at = |i: isize| -> f64 {
+    let mirrored = if i < 0 {
+      -i
+    } else if i >= n {
+      2 * (n - 1) - i
+    } else {
+      i
+    };
+    ys[mirrored as usize]
+  };
+  
+  (0..n)
+    .map(|i| {
+      kernel.iter().enumerate()
+        .map(|(k, w)| { w * at(i + k as isize - half) })
+        .sum()
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn points( ints: &[f64] ) -> Vec<MSPoint> {
+    ints.iter().enumerate()
+      .map(|(i, int)| { MSPoint { mz: 100.0 + i as f64 * 0.1, int: *int, snr: 0.0 } })
+      .collect()
+  }
+
+  fn smoothing( method: Method, width: f64 ) -> Smoothing {
+    Smoothing { method, width, ..Smoothing::default() }
+  }
+
+  #[test]
+  fn savitzky_golay_weights() {
+    let kernel = smoothing(Method::SavitzkyGolay, 5.0).kernel(2);
+    let expected = [-3.0, 12.0, 17.0, 12.0, -3.0].map(|w| { w / 35.0 });
+    for (w, e) in kernel.iter().zip(expected.iter()) {
+      assert!((w - e).abs() < 1e-9);
+    }
+  }
+
+  #[test]
+  fn kernels_add_up_to_one() {
+    for method in Method::ALL {
+      let kernel = smoothing(method, 9.0).kernel(4);
+      assert_eq!(kernel.len(), 9);
+      assert!((kernel.iter().sum::<f64>() - 1.0).abs() < 1e-9, "{}", method);
+      assert!((kernel[0] - kernel[8]).abs() < 1e-12, "{}", method);
+    }
+  }
+
+  // mirroring the ends keeps them from sagging
+  #[test]
+  fn keeps_constant_at_edges() {
+    let pts = points(&[4.0; 10]);
+    for method in Method::ALL {
+      for int in smoothing(method, 7.0).apply(&pts) {
+        assert!((int - 4.0).abs() < 1e-9, "{} gives {}", method, int);
+      }
+    }
+  }
+
+  #[test]
+  fn savitzky_golay_keeps_parabola() {
+    let ints: Vec<f64> = (0..20).map(|i| { (i as f64 - 8.0).powi(2) }).collect();
+    let smoothed = smoothing(Method::SavitzkyGolay, 7.0).apply(&points(&ints));
+    for i in 3..17 {
+      assert!((smoothed[i] - ints[i]).abs() < 1e-9);
+    }
+  }
+
+  #[test]
+  fn window_never_wider_than_spectrum() {
+    let pts = points(&[1.0, 5.0, 2.0, 8.0]);
+    assert_eq!(smoothing(Method::Gaussian, 101.0).half_width(&pts), 3);
+    for method in Method::ALL {
+      assert_eq!(smoothing(method, 101.0).apply(&pts).len(), 4);
+    }
+    assert_eq!(smoothing(Method::Gaussian, 5.0).apply(&pts[..2]), vec![1.0, 5.0]);
+  }
+
+  #[test]
+  fn converts_mz_to_points() {
+    let pts = points(&[0.0; 20]);
+    let s = Smoothing { unit: Unit::Mz, ..smoothing(Method::MovingAverage, 0.5) };
+    assert_eq!(s.half_width(&pts), 2);
+  }
+}
@@ -0,0 +1,238 @@
+
+//! Writing Datasets as peak lists, MGF, mzXML and mzML.
+
+pub mod mgf;
+pub mod peaklist;
+pub mod mzxml;
+pub mod mzml;
+
+use crate::Dataset;
+
+use std::io::{ self, Write };
+
+use base64::encode_config;
+use byteorder::{ BigEndian, LittleEndian, ByteOrder, WriteBytesExt };
+use flate2::{ Compression, write::ZlibEncoder };
+
+const CONFIG: base64::Config = base64::Config::new(base64::CharacterSet::Standard, true);
+
+/// The file formats a Dataset can be written as
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Format {
+  PeaksCsv,
+  PeaksTsv,
+  MgfPeaks,
+  MgfPoints,
+  MzXml,
+  MzMl,
+}
+
+impl Format {
+  pub const ALL: [Format; 6] = [
+    Format::PeaksCsv,
+    Format::PeaksTsv,
+    Format::MgfPeaks,
+    Format::MgfPoints,
+    Format::MzXml,
+    Format::MzMl,
+  ];
+  
+  /// The usual file extension, without the dot
+  pub fn extension(&self) -> &'static str {
+    match self {
+      Format::PeaksCsv  => "csv",
+      Format::PeaksTsv  => "tsv",
+      Format::MgfPeaks |
+      Format::MgfPoints => "mgf",
+      Format::MzXml     => "mzXML",
+      Format::MzMl      => "mzML",
+    }
+  }
+  
+  /// Whether the Encoding matters for this format
+  pub fn is_binary(&self) -> bool {
+    *self == Format::MzXml || *self == Format::MzMl
+  }
+}
+
+impl std::fmt::Display for Format {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        Format::PeaksCsv  => "Peak List (CSV)",
+        Format::PeaksTsv  => "Peak List (TSV)",
+        Format::MgfPeaks  => "MGF (picked peaks)",
+        Format::MgfPoints => "MGF (all points)",
+        Format::MzXml     => "mzXML",
+        Format::MzMl      => "mzML",
+      }
+    )
+  }
+}
+
+/// How the binary arrays of mzXML and mzML files are written
+#[derive(Debug, Clone, Copy)]
+pub struct Encoding {
+  /// 64-bit instead of 32-bit floats
+  pub double: bool,
+  pub zlib:   bool,
+}
+
+impl Default for Encoding {
+  fn default() -> Self {
+    Encoding {
+      double: true,
+      zlib:   true,
+    }
+  }
+}
+
+/// Writes a Dataset to the file s
+pub fn write_file( 
+  ds:       &Dataset, 
+  s:        &String, 
+  format:   Format, 
+  encoding: Encoding 
+) -> io::Result<()> {
+  match format {
+    Format::PeaksCsv  => peaklist::write_peak_list(ds, s, ','),
+    Format::PeaksTsv  => peaklist::write_peak_list(ds, s, '\t'),
+    Format::MgfPeaks  => mgf::write_mgf(ds, s, true),
+    Format::MgfPoints => mgf::write_mgf(ds, s, false),
+    Format::MzXml     => mzxml::write_mzxml(ds, s, encoding),
+    Format::MzMl      => mzml::write_mzml(ds, s, encoding),
+  }
+}
+
+// Packs, optionally compresses and base64-encodes an array of floats.
+// Also returns the number of bytes before base64 encoding.
+fn encode_floats( 
+  values:     &Vec<f64>, 
+  big_endian: bool, 
+  encoding:   Encoding 
+) -> io::Result<(String, usize)> {
+
+  let mut bytes = if big_endian {
+    write_floats::<BigEndian>(values, encoding.double)?
+  } else {
+    write_floats::<LittleEndian>(values, encoding.double)?
+  };
+  
+  if encoding.zlib {
+    let mut z = ZlibEncoder::new(Vec::new(), Compression::default());
+    z.write_all(&bytes)?;
+    bytes = z.finish()?;
+  }
+  
+  Ok((encode_config(&bytes, CONFIG), bytes.len()))
+}
+
+fn write_floats<B: ByteOrder>( values: &Vec<f64>, double: bool ) -> io::Result<Vec<u8>> {
+
+  let mut bytes = Vec::with_capacity(values.len() * if double { 8 } else { 4 });
+  
+  for v in values {
+    if double {
+      bytes.write_f64::<B>(*v)?;
+    } else {
+      bytes.write_f32::<B>(*v as f32)?;
+    }
+  }
+  
+  Ok(bytes)
+}
+
+// escapes text for use in XML attributes and elements
+fn escape( s: &str ) -> String {
+  s.replace('&', "&amp;")
+   .replace('<', "&lt;")
+   .replace('>', "&gt;")
+   .replace('"', "&quot;")
+   .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ Metadata, MSPoint };
+  use crate::parser::{ Progress, parse_file, load_scans };
+
+  fn example() -> Dataset {
+    let points = (0..200)
+      .map(|i| { MSPoint { mz: 500.0 + i as f64 * 0.0123, int: (i * 37 % 101) as f64 + 0.25, snr: 0.0 } })
+      .collect();
+    let md = Metadata {
+      source:         "/data/raw/sample.raw".to_string(),
+      instrument:     "autoflex".to_string(),
+      operator:       "Jane Doe".to_string(),
+      contact:        "jane@example.org".to_string(),
+      processing:     vec!["Smoothing (Gaussian, 7 points)".to_string(), "Baseline & <stuff>".to_string()],
+      precursor_mz:   Some(812.4),
+      charge:         Some(2),
+      retention_time: Some(61.5),
+      ..Metadata::default()
+    };
+    Dataset::new(md, points)
+  }
+
+  fn read_back( s: &String ) -> Dataset {
+    let progress = Progress::default();
+    let mut run = parse_file(s, &progress).unwrap();
+    load_scans(&mut run, 1, 1, &progress).unwrap();
+    run.to_dataset(1, 1).unwrap()
+  }
+
+  // Writes the example in every encoding and reads it back. software is
+  // what the format lists in the processing history ahead of the steps.
+  fn round_trip( format: Format, software: &[String] ) {
+    let ds = example();
+
+    for double in [true, false] {
+      for zlib in [true, false] {
+        let s = std::env::temp_dir()
+          .join(format!("moremass_{}_{}_{}.{}", std::process::id(), double, zlib, format.extension()))
+          .to_string_lossy()
+          .to_string();
+        write_file(&ds, &s, format, Encoding { double, zlib }).unwrap();
+        let back = read_back(&s);
+        std::fs::remove_file(&s).unwrap();
+
+        let tolerance = if double { 0.0 } else { 1e-6 };
+        assert_eq!(back.points.len(), ds.points.len());
+        for (a, b) in ds.points.iter().zip(back.points.iter()) {
+          assert!((a.mz  - b.mz).abs()  <= a.mz.abs()  * tolerance);
+          assert!((a.int - b.int).abs() <= a.int.abs() * tolerance);
+        }
+
+        let (md, back_md) = (&ds.metadata, &back.metadata);
+        assert_eq!(back_md.source,         md.source);
+        assert_eq!(back_md.instrument,     md.instrument);
+        assert_eq!(back_md.operator,       md.operator);
+        assert_eq!(back_md.contact,        md.contact);
+        assert_eq!(back_md.precursor_mz,   md.precursor_mz);
+        assert_eq!(back_md.charge,         md.charge);
+        assert_eq!(back_md.retention_time, md.retention_time);
+
+        let steps = &back_md.processing;
+        assert_eq!(&steps[..software.len()], software);
+        assert_eq!(&steps[software.len()..], &md.processing[..]);
+      }
+    }
+  }
+
+  #[test]
+  fn mzxml_round_trip() {
+    let version = env!("CARGO_PKG_VERSION");
+    round_trip(Format::MzXml, &[
+      "unknown unknown (acquisition)".to_string(),
+      format!("MoreMass {} (processing)", version),
+    ]);
+  }
+
+  #[test]
+  fn mzml_round_trip() {
+    round_trip(Format::MzMl, &[format!("MoreMass {}", env!("CARGO_PKG_VERSION"))]);
+  }
+}
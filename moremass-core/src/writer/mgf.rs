@@ -0,0 +1,41 @@
+
+use crate::Dataset;
+
+use std::fs::File;
+use std::io::{ self, BufWriter, Write };
+
+// Writes a Dataset as a single BEGIN IONS ... END IONS block, either with its
+// picked peaks or with all of its points.
+pub fn write_mgf( ds: &Dataset, s: &String, peaks_only: bool ) -> io::Result<()> {
+
+  let mut out = BufWriter::new(File::create(s)?);
+  let md = &ds.metadata;
+
+  writeln!(out, "BEGIN IONS")?;
+  writeln!(out, "TITLE={}", md.title)?;
+
+  if let Some(mz) = md.precursor_mz {
+    writeln!(out, "PEPMASS={}", mz)?;
+  }
+  if let Some(z) = md.charge {
+    writeln!(out, "CHARGE={}{}", z.abs(), if z < 0 { "-" } else { "+" })?;
+  }
+  if let Some(rt) = md.retention_time {
+    writeln!(out, "RTINSECONDS={}", rt)?;
+  }
+
+  // peaks are written with their fitted apex
+  let mut points: Vec<(f64, f64)> = if peaks_only {
+    ds.peaks.iter().map(|p| { (p.mz, p.int) }).collect()
+  } else {
+    ds.points.iter().map(|pt| { (pt.mz, pt.int) }).collect()
+  };
+  points.sort_by(|a, b| { a.0.partial_cmp(&b.0).unwrap() });
+
+  for (mz, int) in points {
+    writeln!(out, "{} {}", mz, int)?;
+  }
+
+  writeln!(out, "END IONS")?;
+  out.flush()
+}
@@ -0,0 +1,209 @@
+
+use crate::Dataset;
+use crate::writer::{ Encoding, encode_floats, escape };
+
+use std::fs::File;
+use std::path::Path;
+use std::io::{ self, BufWriter, Write };
+
+// Writes a Dataset as an mzML 1.1 file holding a single spectrum. Terms that
+// are not part of the PSI-MS vocabulary, like the instrument description and
+// the processing history, are written as userParams.
+pub fn write_mzml( ds: &Dataset, s: &String, encoding: Encoding ) -> io::Result<()> {
+
+  let mut out = BufWriter::new(File::create(s)?);
+  let md = &ds.metadata;
+  
+  let ms_level = if md.precursor_mz.is_some() { 2 } else { 1 };
+  let base_peak = ds.points.iter()
+    .fold(&ds.points[0], |best, pt| { if pt.int > best.int { pt } else { best } });
+  let tic: f64 = ds.points.iter().map(|pt| { pt.int }).sum();
+  
+  let mzs:  Vec<f64> = ds.points.iter().map(|pt| { pt.mz  }).collect();
+  let ints: Vec<f64> = ds.points.iter().map(|pt| { pt.int }).collect();
+
+  writeln!(out, "<?xml version=\"1.0\" encoding=\"utf-8\"?>")?;
+  writeln!(out, "<mzML xmlns=\"http://psi.hupo.org/ms/mzml\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:schemaLocation=\"http://psi.hupo.org/ms/mzml http://psidev.info/files/ms/mzML/xsd/mzML1.1.0.xsd\" version=\"1.1.0\">")?;
+  writeln!(out, "  <cvList count=\"2\">")?;
+  writeln!(out, "    <cv id=\"MS\" fullName=\"Proteomics Standards Initiative Mass Spectrometry Ontology\" URI=\"https://raw.githubusercontent.com/HUPO-PSI/psi-ms-CV/master/psi-ms.obo\"/>")?;
+  writeln!(out, "    <cv id=\"UO\" fullName=\"Unit Ontology\" URI=\"https://raw.githubusercontent.com/bio-ontology-research-group/unit-ontology/master/unit.obo\"/>")?;
+  writeln!(out, "  </cvList>")?;
+  
+  // FILE DESCRIPTION ----------------------------
+  writeln!(out, "  <fileDescription>")?;
+  writeln!(out, "    <fileContent>")?;
+  if ms_level == 1 {
+    cv_param(&mut out, 6, "MS:1000579", "MS1 spectrum", "")?;
+  } else {
+    cv_param(&mut out, 6, "MS:1000580", "MSn spectrum", "")?;
+  }
+  writeln!(out, "    </fileContent>")?;
+  
+  if !md.source.is_empty() {
+    let source = Path::new(&md.source);
+    let name = source.file_name()
+      .map(|n| { n.to_string_lossy().to_string() })
+      .unwrap_or(md.source.clone());
+    let location = md.source[..md.source.len() - name.len()].to_string();
+    
+    writeln!(out, "    <sourceFileList count=\"1\">")?;
+    writeln!(
+      out, 
+      "      <sourceFile id=\"SF1\" name=\"{}\" location=\"{}\"/>", 
+      escape(&name), escape(&location)
+    )?;
+    writeln!(out, "    </sourceFileList>")?;
+  }
+  
+  if !md.operator.is_empty() || !md.institution.is_empty() || !md.contact.is_empty() {
+    writeln!(out, "    <contact>")?;
+    cv_param(&mut out, 6, "MS:1000586", "contact name",        &md.operator)?;
+    cv_param(&mut out, 6, "MS:1000590", "contact affiliation", &md.institution)?;
+    if !md.contact.is_empty() {
+      cv_param(&mut out, 6, "MS:1000589", "contact email",     &md.contact)?;
+    }
+    writeln!(out, "    </contact>")?;
+  }
+  writeln!(out, "  </fileDescription>")?;
+  
+  // SOFTWARE, INSTRUMENT, PROCESSING ------------
+  writeln!(out, "  <softwareList count=\"1\">")?;
+  writeln!(out, "    <software id=\"moremass\" version=\"{}\">", env!("CARGO_PKG_VERSION"))?;
+  user_param(&mut out, 6, "MoreMass")?;
+  writeln!(out, "    </software>")?;
+  writeln!(out, "  </softwareList>")?;
+  
+  writeln!(out, "  <instrumentConfigurationList count=\"1\">")?;
+  writeln!(out, "    <instrumentConfiguration id=\"IC1\">")?;
+  if md.instrument.is_empty() {
+    cv_param(&mut out, 6, "MS:1000031", "instrument model", "")?;
+  } else {
+    user_param(&mut out, 6, &md.instrument)?;
+  }
+  writeln!(out, "    </instrumentConfiguration>")?;
+  writeln!(out, "  </instrumentConfigurationList>")?;
+  
+  writeln!(out, "  <dataProcessingList count=\"1\">")?;
+  writeln!(out, "    <dataProcessing id=\"moremass_processing\">")?;
+  if md.processing.is_empty() {
+    writeln!(out, "      <processingMethod order=\"0\" softwareRef=\"moremass\">")?;
+    cv_param(&mut out, 8, "MS:1000544", "Conversion to mzML", "")?;
+    writeln!(out, "      </processingMethod>")?;
+  }
+  for (i, step) in md.processing.iter().enumerate() {
+    writeln!(out, "      <processingMethod order=\"{}\" softwareRef=\"moremass\">", i)?;
+    user_param(&mut out, 8, step)?;
+    writeln!(out, "      </processingMethod>")?;
+  }
+  writeln!(out, "    </dataProcessing>")?;
+  writeln!(out, "  </dataProcessingList>")?;
+  
+  // SPECTRUM ------------------------------------
+  writeln!(
+    out, 
+    "  <run id=\"run1\" defaultInstrumentConfigurationRef=\"IC1\" startTimeStamp=\"{}\">",
+    md.date.to_rfc3339()
+  )?;
+  writeln!(out, "    <spectrumList count=\"1\" defaultDataProcessingRef=\"moremass_processing\">")?;
+  writeln!(
+    out, 
+    "      <spectrum index=\"0\" id=\"scan=1\" defaultArrayLength=\"{}\">",
+    ds.points.len()
+  )?;
+  cv_param(&mut out, 8, "MS:1000511", "ms level", &ms_level.to_string())?;
+  cv_param(&mut out, 8, "MS:1000128", "profile spectrum", "")?;
+  cv_param(&mut out, 8, "MS:1000528", "lowest observed m/z",  &ds.mz_min.to_string())?;
+  cv_param(&mut out, 8, "MS:1000527", "highest observed m/z", &ds.mz_max.to_string())?;
+  cv_param(&mut out, 8, "MS:1000504", "base peak m/z",        &base_peak.mz.to_string())?;
+  cv_param(&mut out, 8, "MS:1000505", "base peak intensity",  &base_peak.int.to_string())?;
+  cv_param(&mut out, 8, "MS:1000285", "total ion current",    &tic.to_string())?;
+  
+  if let Some(rt) = md.retention_time {
+    writeln!(out, "        <scanList count=\"1\">")?;
+    cv_param(&mut out, 10, "MS:1000795", "no combination", "")?;
+    writeln!(out, "          <scan>")?;
+    writeln!(
+      out, 
+      "            <cvParam cvRef=\"MS\" accession=\"MS:1000016\" name=\"scan start time\" value=\"{}\" unitCvRef=\"UO\" unitAccession=\"UO:0000010\" unitName=\"second\"/>",
+      rt
+    )?;
+    writeln!(out, "          </scan>")?;
+    writeln!(out, "        </scanList>")?;
+  }
+  
+  if let Some(mz) = md.precursor_mz {
+    writeln!(out, "        <precursorList count=\"1\">")?;
+    writeln!(out, "          <precursor>")?;
+    writeln!(out, "            <selectedIonList count=\"1\">")?;
+    writeln!(out, "              <selectedIon>")?;
+    cv_param(&mut out, 16, "MS:1000744", "selected ion m/z", &mz.to_string())?;
+    if let Some(z) = md.charge {
+      cv_param(&mut out, 16, "MS:1000041", "charge state", &z.abs().to_string())?;
+    }
+    writeln!(out, "              </selectedIon>")?;
+    writeln!(out, "            </selectedIonList>")?;
+    writeln!(out, "            <activation/>")?;
+    writeln!(out, "          </precursor>")?;
+    writeln!(out, "        </precursorList>")?;
+  }
+  
+  writeln!(out, "        <binaryDataArrayList count=\"2\">")?;
+  binary_array(&mut out, &mzs,  ("MS:1000514", "m/z array"),       encoding)?;
+  binary_array(&mut out, &ints, ("MS:1000515", "intensity array"), encoding)?;
+  writeln!(out, "        </binaryDataArrayList>")?;
+  
+  writeln!(out, "      </spectrum>")?;
+  writeln!(out, "    </spectrumList>")?;
+  writeln!(out, "  </run>")?;
+  writeln!(out, "</mzML>")?;
+  
+  out.flush()
+}
+
+fn binary_array( 
+  out:      &mut impl Write, 
+  values:   &Vec<f64>, 
+  kind:     (&str, &str), 
+  encoding: Encoding 
+) -> io::Result<()> {
+
+  // mzML arrays are always little-endian
+  let (binary, _) = encode_floats(values, false, encoding)?;
+  
+  writeln!(out, "          <binaryDataArray encodedLength=\"{}\">", binary.len())?;
+  if encoding.double {
+    cv_param(out, 12, "MS:1000523", "64-bit float", "")?;
+  } else {
+    cv_param(out, 12, "MS:1000521", "32-bit float", "")?;
+  }
+  if encoding.zlib {
+    cv_param(out, 12, "MS:1000574", "zlib compression", "")?;
+  } else {
+    cv_param(out, 12, "MS:1000576", "no compression", "")?;
+  }
+  cv_param(out, 12, kind.0, kind.1, "")?;
+  writeln!(out, "            <binary>{}</binary>", binary)?;
+  writeln!(out, "          </binaryDataArray>")
+}
+
+fn cv_param( 
+  out:       &mut impl Write, 
+  indent:    usize, 
+  accession: &str, 
+  name:      &str, 
+  value:     &str 
+) -> io::Result<()> {
+  writeln!(
+    out,
+    "{:indent$}<cvParam cvRef=\"MS\" accession=\"{}\" name=\"{}\" value=\"{}\"/>",
+    "", accession, name, escape(value), indent = indent
+  )
+}
+
+fn user_param( out: &mut impl Write, indent: usize, name: &str ) -> io::Result<()> {
+  writeln!(
+    out, 
+    "{:indent$}<userParam name=\"{}\"/>", 
+    "", escape(name), indent = indent
+  )
+}
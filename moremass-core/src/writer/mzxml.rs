@@ -0,0 +1,172 @@
+
+use crate::Dataset;
+use crate::writer::{ Encoding, encode_floats, escape };
+
+use std::fs::File;
+use std::io::{ self, BufRead, BufReader, BufWriter, Write };
+
+// Writes a Dataset as an mzXML 3.2 file holding a single scan. The processing
+// history ends up as <processingOperation>s.
+pub fn write_mzxml( ds: &Dataset, s: &String, encoding: Encoding ) -> io::Result<()> {
+
+  let md = &ds.metadata;
+  let parent = if md.source.is_empty() { &md.path } else { &md.source };
+  // hashed before s is created, in case it is the parent itself
+  let parent_sha1 = file_sha1(parent);
+
+  let mut out = BufWriter::new(File::create(s)?);
+
+  // mzXML stores the values as interleaved (m/z, intensity) pairs
+  let values: Vec<f64> = 
+    ds.points.iter()
+      .flat_map(|pt| { [pt.mz, pt.int] })
+      .collect();
+  let (peaks, compressed_len) = encode_floats(&values, true, encoding)?;
+  
+  let base_peak = ds.points.iter()
+    .fold(&ds.points[0], |best, pt| { if pt.int > best.int { pt } else { best } });
+  let tic: f64 = ds.points.iter().map(|pt| { pt.int }).sum();
+  let rt = md.retention_time.unwrap_or(0.0);
+  let rt_attribute = match md.retention_time {
+    Some(t) => format!(" retentionTime=\"PT{}S\"", t),
+    None    => String::new(),
+  };
+
+  writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+  writeln!(out, "<mzXML xmlns=\"http://sashimi.sourceforge.net/schema_revision/mzXML_3.2\"")?;
+  writeln!(out, "       xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\"")?;
+  writeln!(out, "       xsi:schemaLocation=\"http://sashimi.sourceforge.net/schema_revision/mzXML_3.2 http://sashimi.sourceforge.net/schema_revision/mzXML_3.2/mzXML_3.2.xsd\">")?;
+  writeln!(out, "  <msRun scanCount=\"1\" startTime=\"PT{}S\" endTime=\"PT{}S\">", rt, rt)?;
+  
+  writeln!(
+    out, 
+    "    <parentFile fileName=\"{}\" fileType=\"RAWData\" fileSha1=\"{}\"/>",
+    escape(parent), parent_sha1
+  )?;
+  
+  // only the model is known of the instrument, but the schema requires the
+  // other parts and the acquisition software to be named as well
+  writeln!(out, "    <msInstrument msInstrumentID=\"1\">")?;
+  writeln!(out, "      <msManufacturer category=\"msManufacturer\" value=\"\"/>")?;
+  writeln!(out, "      <msModel category=\"msModel\" value=\"{}\"/>", escape(&md.instrument))?;
+  writeln!(out, "      <msIonisation category=\"msIonisation\" value=\"\"/>")?;
+  writeln!(out, "      <msMassAnalyzer category=\"msMassAnalyzer\" value=\"\"/>")?;
+  writeln!(out, "      <software type=\"acquisition\" name=\"unknown\" version=\"unknown\"/>")?;
+  if !md.operator.is_empty() || !md.contact.is_empty() {
+    let (first, last) = md.operator.split_once(' ').unwrap_or((&md.operator, ""));
+    writeln!(
+      out, 
+      "      <operator first=\"{}\" last=\"{}\" email=\"{}\"/>", 
+      escape(first), escape(last), escape(&md.contact)
+    )?;
+  }
+  writeln!(out, "    </msInstrument>")?;
+  
+  writeln!(out, "    <dataProcessing centroided=\"0\">")?;
+  writeln!(
+    out, 
+    "      <software type=\"processing\" name=\"MoreMass\" version=\"{}\"/>",
+    env!("CARGO_PKG_VERSION")
+  )?;
+  for step in &md.processing {
+    writeln!(out, "      <processingOperation name=\"{}\"/>", escape(step))?;
+  }
+  writeln!(out, "    </dataProcessing>")?;
+  
+  writeln!(
+    out,
+    "    <scan num=\"1\" msLevel=\"{}\" peaksCount=\"{}\"{} lowMz=\"{}\" highMz=\"{}\" basePeakMz=\"{}\" basePeakIntensity=\"{}\" totIonCurrent=\"{}\">",
+    if md.precursor_mz.is_some() { 2 } else { 1 },
+    ds.points.len(), rt_attribute, ds.mz_min, ds.mz_max, 
+    base_peak.mz, base_peak.int, tic
+  )?;
+  
+  // the precursor's intensity is required, but not known
+  if let Some(mz) = md.precursor_mz {
+    match md.charge {
+      Some(z) => writeln!(
+        out, "      <precursorMz precursorIntensity=\"0\" precursorCharge=\"{}\">{}</precursorMz>", z.abs(), mz
+      )?,
+      None    => writeln!(out, "      <precursorMz precursorIntensity=\"0\">{}</precursorMz>", mz)?,
+    }
+  }
+  
+  writeln!(
+    out,
+    "      <peaks precision=\"{}\" byteOrder=\"network\" contentType=\"m/z-int\" compressionType=\"{}\" compressedLen=\"{}\">{}</peaks>",
+    if encoding.double { 64 } else { 32 },
+    if encoding.zlib { "zlib" } else { "none" },
+    if encoding.zlib { compressed_len } else { 0 },
+    peaks
+  )?;
+  
+  writeln!(out, "    </scan>")?;
+  writeln!(out, "  </msRun>")?;
+  writeln!(out, "</mzXML>")?;
+  
+  out.flush()
+}
+
+// The SHA-1 of a file as hex, or all zeros like other converters write when
+// the file is not at hand
+fn file_sha1( s: &String ) -> String {
+  match read_sha1(s) {
+    Err(_)   => "0".repeat(40),
+    Ok(sha1) => sha1,
+  }
+}
+
+// raw files can be several GB, so they are hashed a buffer at a time
+fn read_sha1( s: &String ) -> io::Result<String> {
+  let mut file = BufReader::new(File::open(s)?);
+  let mut sha1 = sha1_smol::Sha1::new();
+
+  loop {
+    let chunk = file.fill_buf()?;
+    if chunk.is_empty() {
+      break;
+    }
+    sha1.update(chunk);
+    let n = chunk.len();
+    file.consume(n);
+  }
+
+  Ok(sha1.digest().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{ Metadata, MSPoint };
+  use crate::parser::tests::temp_file;
+
+  // a spectrum without any metadata still gets every element the schema
+  // requires
+  #[test]
+  fn writes_required_elements() {
+    let parent = temp_file("parent.raw", b"abc");
+    let md = Metadata {
+      source:       parent.clone(),
+      precursor_mz: Some(500.0),
+      ..Metadata::default()
+    };
+    let points = (0..3).map(|i| { MSPoint { mz: 100.0 + i as f64, int: 1.0, snr: 0.0 } }).collect();
+    let s = temp_file("required.mzXML", b"");
+
+    write_mzxml(&Dataset::new(md, points), &s, Encoding::default()).unwrap();
+    let text = std::fs::read_to_string(&s).unwrap();
+    std::fs::remove_file(&s).unwrap();
+    std::fs::remove_file(&parent).unwrap();
+
+    for required in [
+      "mzXML_3.2/mzXML_3.2.xsd",
+      "fileSha1=\"a9993e364706816aba3e25717850c26c9cd0d89d\"",
+      "<msManufacturer ", "<msModel ", "<msIonisation ", "<msMassAnalyzer ",
+      "<software type=\"acquisition\"",
+      "<precursorMz precursorIntensity=",
+    ] {
+      assert!(text.contains(required), "{} is missing", required);
+    }
+    assert!(!text.contains("<operator"));
+  }
+}
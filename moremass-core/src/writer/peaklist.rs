@@ -0,0 +1,67 @@
+
+use crate::Dataset;
+
+use std::fs::File;
+use std::io::{ self, BufWriter, Write };
+
+const COLUMNS: [&str; 9] = [
+  "m/z", "intensity", "s/n", "rel. intensity", "fwhm", "resolution", "area", "charge", "annotation",
+];
+
+// Writes the picked peaks of a Dataset as a table, one peak per line and
+// sorted by m/z. m/z and intensity are those of the fitted apex. Relative
+// intensities are given in percent of the highest peak. Attributes that are
+// not known are left empty.
+pub fn write_peak_list( ds: &Dataset, s: &String, delimiter: char ) -> io::Result<()> {
+
+  let mut out = BufWriter::new(File::create(s)?);
+
+  let mut peaks = ds.peaks.clone();
+  peaks.sort_by(|a, b| { a.mz.partial_cmp(&b.mz).unwrap() });
+
+  let max_int = peaks.iter()
+    .map(|p| { p.int })
+    .fold(0.0, f64::max);
+
+  writeln!(out, "{}", COLUMNS.join(&delimiter.to_string()))?;
+
+  for p in peaks {
+    let rel_int = if max_int > 0.0 { p.int / max_int * 100.0 } else { 0.0 };
+    let annotation = ds.annotation(p.index);
+    let if_known = |v: f64, precision: usize| -> String {
+      if p.fwhm > 0.0 { format!("{:.*}", precision, v) } else { String::new() }
+    };
+
+    let fields = [
+      format!("{:.6}", p.mz),
+      format!("{:.2}", p.int),
+      format!("{:.2}", ds.points[p.index].snr),
+      format!("{:.2}", rel_int),
+      if_known(p.fwhm, 6),
+      if_known(p.resolution(), 0),
+      format!("{:.2}", p.area),
+      annotation.and_then(|a| { a.charge }).map(|z| { z.to_string() }).unwrap_or_default(),
+      annotation.map(|a| { a.label.clone() }).unwrap_or_default(),
+    ];
+
+    writeln!(
+      out,
+      "{}",
+      fields.iter()
+        .map(|f| { quote(f, delimiter) })
+        .collect::<Vec<String>>()
+        .join(&delimiter.to_string())
+    )?;
+  }
+
+  out.flush()
+}
+
+// quotes a field if it would otherwise break the table
+fn quote( field: &str, delimiter: char ) -> String {
+  if field.contains(delimiter) || field.contains('"') || field.contains('\n') {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
@@ -0,0 +1,438 @@
+/*
+ * Batch mode: reads each input file, runs the processing steps given on the
+ * command line and writes the result, without opening a window.
+ */
+
+use crate::backend::{
+  Centroiding, Dataset,
+  baseline::{ self, Baseline },
+  cwt::{ Cwt, MIN_SCALE },
+  smoothing::{ self, Smoothing, Unit },
+  parser::{ Progress, parse_file, load_scans },
+  writer::{ Encoding, Format, write_file },
+};
+
+use crate::config::{ Config, Settings };
+
+use std::path::{ Path, PathBuf };
+
+const USAGE: &str = "\
+Usage: moremass --batch [OPTIONS] FILE...
+
+Processing steps are run in the order they are given. Defaults are taken
+from the settings of the graphical interface.
+
+Steps:
+  --smooth METHOD,WIDTH[,ORDER]
+                              smooth with sg (Savitzky-Golay, of ORDER), gauss
+                              or avg over WIDTH points, or WIDTH m/z if it
+                              ends in mz (e.g. gauss,0.5mz)
+  --baseline METHOD,WIDTH[,DEGREE]
+                              subtract a baseline estimated with snip, tophat
+                              or poly over WIDTH points (poly: of DEGREE)
+  --find-peaks RATIO,ABS,REL  pick peaks above a S/N ratio, an absolute and a
+                              relative intensity (0 to 1); values left empty,
+                              as in 3,,0.05, are taken from the settings
+  --find-peaks-cwt RATIO,ABS,REL[,MIN,MAX]
+                              the same, but with peaks found by a wavelet
+                              transform over scales of MIN to MAX points
+                              (default: 1 to 32); RATIO applies to the S/N of
+                              the transform
+
+Options:
+  --scans FROM-TO   scans to sum for files holding several (default: all)
+  --noise-window MZ width of the windows noise is estimated in for the S/N
+                    (default: as set, else a twentieth of the m/z range)
+  --centroid METHOD how the apex of picked peaks is located: parabolic,
+                    gaussian or halfheight (default: as set)
+  --format FORMAT   csv, tsv, mgf, mgf-points, mzxml or mzml (default: csv)
+  --out DIR         directory to write to (default: next to each input)
+  --single          write 32-bit floats to mzXML and mzML
+  --no-zlib         do not compress the arrays of mzXML and mzML
+  --help            show this message";
+
+// A processing step, applied to every dataset
+#[derive(Debug, Clone, Copy)]
+enum Step {
+  Smooth(Smoothing),
+  Baseline(Baseline),
+  FindPeaks(f64, f64, f64),
+  FindPeaksCwt(Cwt, f64, f64, f64),
+}
+
+#[derive(Debug)]
+struct Options {
+  inputs:       Vec<String>,
+  steps:        Vec<Step>,
+  scans:        Option<(u32, u32)>,
+  noise_window: Option<f64>,
+  centroiding:  Centroiding,
+  format:       Format,
+  encoding:     Encoding,
+  out_dir:      Option<PathBuf>,
+}
+
+// Runs the batch and returns the exit code: 0 if every file went through,
+// 1 if some failed and 2 for bad arguments
+pub fn run(args: &[String]) -> i32 {
+
+  let options = match parse_args(args, &Config::load().settings) {
+    Err(why) if why.is_empty() => {
+      println!("{}", USAGE);
+      return 0;
+    }
+    Err(why) => {
+      eprintln!("{}\n\n{}", why, USAGE);
+      return 2;
+    }
+    Ok(o) => o
+  };
+
+  let mut failed = 0;
+
+  for (input, output) in options.inputs.iter().zip(output_paths(&options)) {
+    match process(input, output, &options) {
+      Err(why) => {
+        eprintln!("{}: {}", input, why);
+        failed += 1;
+      }
+      Ok((output, peaks)) => {
+        println!("{} -> {} ({} peaks)", input, output.display(), peaks);
+      }
+    }
+  }
+
+  if failed > 0 {
+    eprintln!("{} of {} files failed", failed, options.inputs.len());
+    1
+  } else {
+    0
+  }
+}
+
+// Options not given on the command line come from the settings. An empty
+// error means help was asked for.
+fn parse_args(args: &[String], settings: &Settings) -> Result<Options, String> {
+
+  let thresholds = [settings.sn_ratio, settings.abs_intensity, settings.rel_intensity];
+
+  let mut options = Options {
+    inputs:       vec![],
+    steps:        vec![],
+    scans:        None,
+    noise_window: if settings.noise_window > 0.0 { Some(settings.noise_window) } else { None },
+    centroiding:  settings.centroiding,
+    format:       Format::PeaksCsv,
+    encoding:     Encoding::default(),
+    out_dir:      None,
+  };
+
+  let mut args = args.iter();
+
+  while let Some(arg) = args.next() {
+    let mut value = || -> Result<&String, String> {
+      args.next().ok_or(format!("{} needs a value", arg))
+    };
+
+    match arg.as_str() {
+      "-h" | "--help" => { return Err(String::new()); }
+
+      "--smooth" => {
+        options.steps.push(Step::Smooth(parse_smoothing(value()?)?));
+      }
+
+      "--baseline" => {
+        options.steps.push(Step::Baseline(parse_baseline(value()?)?));
+      }
+
+      "--find-peaks" => {
+        let v = parse_floats(value()?, &thresholds)?;
+        options.steps.push(Step::FindPeaks(v[0], v[1], v[2]));
+      }
+
+      "--find-peaks-cwt" => {
+        let v = value()?;
+        let default = Cwt::default();
+        let v = if v.split(',').count() == 5 {
+          let mut defaults = thresholds.to_vec();
+          defaults.extend([default.min_scale, default.max_scale]);
+          parse_floats(v, &defaults)?
+        } else {
+          parse_floats(v, &thresholds)?
+        };
+
+        let mut cwt = default;
+        if v.len() == 5 {
+          if v[3].is_nan() || v[3] < MIN_SCALE || !v[4].is_finite() || v[4] < v[3] {
+            return Err(format!(
+              "{} to {} are not wavelet scales; they run from at least {} points up", v[3], v[4], MIN_SCALE
+            ));
+          }
+          cwt = Cwt { min_scale: v[3], max_scale: v[4] };
+        }
+        options.steps.push(Step::FindPeaksCwt(cwt, v[0], v[1], v[2]));
+      }
+
+      "--scans" => {
+        let v = value()?;
+        let range = v.split_once('-')
+          .and_then(|(from, to)| { Some((from.trim().parse().ok()?, to.trim().parse().ok()?)) });
+        match range {
+          Some(r) => { options.scans = Some(r); }
+          None    => { return Err(format!("\"{}\" is not a scan range like 3-7", v)); }
+        }
+      }
+
+      "--noise-window" => {
+        let v = value()?;
+        match v.parse() {
+          Ok(w) if w > 0.0 => { options.noise_window = Some(w); }
+          _ => { return Err(format!("\"{}\" is not an m/z width", v)); }
+        }
+      }
+
+      "--centroid" => {
+        let v = value()?;
+        options.centroiding = match v.to_lowercase().as_str() {
+          "parabolic"  => Centroiding::Parabolic,
+          "gaussian"   => Centroiding::Gaussian,
+          "halfheight" => Centroiding::HalfHeight,
+          _ => { return Err(format!("unknown centroiding method \"{}\"", v)); }
+        };
+      }
+
+      "--format" => {
+        let v = value()?;
+        options.format = match v.to_lowercase().as_str() {
+          "csv"        => Format::PeaksCsv,
+          "tsv"        => Format::PeaksTsv,
+          "mgf"        => Format::MgfPeaks,
+          "mgf-points" => Format::MgfPoints,
+          "mzxml"      => Format::MzXml,
+          "mzml"       => Format::MzMl,
+          _ => { return Err(format!("unknown format \"{}\"", v)); }
+        };
+      }
+
+      "--out" => {
+        options.out_dir = Some(PathBuf::from(value()?));
+      }
+
+      "--single"  => { options.encoding.double = false; }
+      "--no-zlib" => { options.encoding.zlib   = false; }
+
+      a if a.starts_with("--") => {
+        return Err(format!("unknown option {}", a));
+      }
+
+      _ => { options.inputs.push(arg.clone()); }
+    }
+  }
+
+  if options.inputs.is_empty() {
+    return Err("no input files given".to_string());
+  }
+
+  Ok(options)
+}
+
+// Reads a comma separated list of as many numbers as there are defaults.
+// Empty values take the default.
+fn parse_floats(s: &str, defaults: &[f64]) -> Result<Vec<f64>, String> {
+
+  let parts: Vec<&str> = s.split(',').map(|v| { v.trim() }).collect();
+
+  if parts.len() != defaults.len() {
+    return Err(format!("\"{}\" should be {} numbers separated by commas", s, defaults.len()));
+  }
+
+  let values: Result<Vec<f64>, _> = parts.iter().zip(defaults.iter())
+    .map(|(v, default)| { if v.is_empty() { Ok(*default) } else { v.parse() } })
+    .collect();
+
+  match values {
+    Err(_) => Err(format!("\"{}\" is not a list of numbers", s)),
+    Ok(v)  => Ok(v)
+  }
+}
+
+// e.g. "sg,9,3" or "gauss,0.5mz"
+fn parse_smoothing(s: &str) -> Result<Smoothing, String> {
+
+  let parts: Vec<&str> = s.split(',').map(|p| { p.trim() }).collect();
+  let mut smoothing = Smoothing::default();
+
+  smoothing.method = match parts[0].to_lowercase().as_str() {
+    "sg"    => smoothing::Method::SavitzkyGolay,
+    "gauss" => smoothing::Method::Gaussian,
+    "avg"   => smoothing::Method::MovingAverage,
+    _ => { return Err(format!("unknown smoothing method \"{}\"", parts[0])); }
+  };
+
+  if parts.len() > 3 {
+    return Err(format!("\"{}\" has too many values for smoothing", s));
+  }
+  if let Some(width) = parts.get(1) {
+    let (number, unit) = match width.strip_suffix("mz") {
+      Some(w) => (w, Unit::Mz),
+      None    => (*width, Unit::Points),
+    };
+    smoothing.unit  = unit;
+    smoothing.width = match number.trim().parse() {
+      Ok(w) if w > 0.0 => w,
+      _ => { return Err(format!("\"{}\" is not a window width", width)); }
+    };
+  }
+  if let Some(order) = parts.get(2) {
+    smoothing.order = match order.parse() {
+      Ok(o) => o,
+      Err(_) => { return Err(format!("\"{}\" is not a polynomial order", order)); }
+    };
+  }
+
+  Ok(smoothing)
+}
+
+// e.g. "snip,40" or "poly,500,3"
+fn parse_baseline(s: &str) -> Result<Baseline, String> {
+
+  let parts: Vec<&str> = s.split(',').map(|p| { p.trim() }).collect();
+  let mut baseline = Baseline::default();
+
+  baseline.method = match parts[0].to_lowercase().as_str() {
+    "snip"   => baseline::Method::Snip,
+    "tophat" => baseline::Method::TopHat,
+    "poly"   => baseline::Method::Polynomial,
+    _ => { return Err(format!("unknown baseline method \"{}\"", parts[0])); }
+  };
+
+  if parts.len() > 3 {
+    return Err(format!("\"{}\" has too many values for a baseline", s));
+  }
+  if let Some(width) = parts.get(1) {
+    baseline.width = match width.parse() {
+      Ok(0) => { return Err(String::from("baseline width must be at least one point")); }
+      Ok(w) => w,
+      Err(_) => { return Err(format!("\"{}\" is not a width in points", width)); }
+    };
+  }
+  if let Some(degree) = parts.get(2) {
+    baseline.degree = match degree.parse() {
+      Ok(d) => d,
+      Err(_) => { return Err(format!("\"{}\" is not a polynomial degree", degree)); }
+    };
+  }
+  if baseline.method == baseline::Method::Polynomial && baseline.width < baseline.degree + 1 {
+    return Err(format!(
+      "segments of {} points are too short for polynomials of degree {}", baseline.width, baseline.degree
+    ));
+  }
+
+  Ok(baseline)
+}
+
+// Returns where the result went and how many peaks it has
+fn process(input: &String, output: PathBuf, options: &Options) -> Result<(PathBuf, usize), String> {
+
+  let progress = Progress::default();
+
+  let mut run = parse_file(input, &progress).map_err(|why| { why.to_string() })?;
+
+  let (from, to) = options.scans.unwrap_or_else(|| {
+    let nums = run.scans.iter().map(|scan| { scan.num });
+    (nums.clone().min().unwrap_or(0), nums.max().unwrap_or(0))
+  });
+
+  load_scans(&mut run, from, to, &progress).map_err(|why| { why.to_string() })?;
+
+  let mut ds = match run.to_dataset(from, to) {
+    Some(ds) => ds,
+    None     => { return Err(format!("no scans in the range {}-{}", from, to)); }
+  };
+
+  if let Some(window) = options.noise_window {
+    ds.set_noise_window(window);
+  }
+  ds.set_centroiding(options.centroiding);
+
+  for step in &options.steps {
+    apply(&mut ds, *step);
+  }
+
+  write_file(&ds, &output.to_string_lossy().to_string(), options.format, options.encoding)
+    .map_err(|why| { why.to_string() })?;
+
+  Ok((output, ds.peaks.len()))
+}
+
+fn apply(ds: &mut Dataset, step: Step) {
+  match step {
+    Step::Smooth(smoothing) => {
+      ds.smooth(&smoothing);
+    }
+    Step::Baseline(baseline) => {
+      ds.subtract_baseline(&baseline);
+    }
+    Step::FindPeaks(ratio, abs_int, rel_int) => {
+      ds.find_peaks(ratio, abs_int, rel_int, true);
+    }
+    Step::FindPeaksCwt(cwt, ratio, abs_int, rel_int) => {
+      ds.find_peaks_cwt(&cwt, ratio, abs_int, rel_int, true);
+    }
+  }
+}
+
+// The inputs' names with the extension of the format, in the directory of
+// each input or --out. Inputs are never overwritten and no two inputs write
+// to the same file: clashing names get _processed, then a number appended.
+fn output_paths(options: &Options) -> Vec<PathBuf> {
+
+  let mut taken: Vec<PathBuf> = options.inputs.iter().map(|s| { resolved(Path::new(s)) }).collect();
+  let ext = options.format.extension();
+
+  options.inputs.iter()
+    .map(|input| {
+      let input = Path::new(input);
+      let dir = match &options.out_dir {
+        Some(d) => d.clone(),
+        None    => input.parent().map(|p| { p.to_path_buf() }).unwrap_or_default(),
+      };
+      let stem = input.file_stem()
+        .map(|s| { s.to_string_lossy().to_string() })
+        .unwrap_or_default();
+
+      let output = (0..)
+        .map(|n| {
+          match n {
+            0 => dir.join(format!("{}.{}", stem, ext)),
+            1 => dir.join(format!("{}_processed.{}", stem, ext)),
+            _ => dir.join(format!("{}_processed_{}.{}", stem, n, ext)),
+          }
+        })
+        .find(|output| { !taken.contains(&resolved(output)) })
+        .unwrap();
+
+      taken.push(resolved(&output));
+      output
+    })
+    .collect()
+}
+
+// The absolute path of a file, following links, so that e.g. a.csv and
+// ./a.csv compare equal. Files that do not exist yet are resolved through
+// their directory.
+fn resolved(path: &Path) -> PathBuf {
+
+  if let Ok(p) = path.canonicalize() {
+    return p;
+  }
+
+  let dir = match path.parent() {
+    Some(d) if !d.as_os_str().is_empty() => d,
+    _ => Path::new("."),
+  };
+  match (dir.canonicalize(), path.file_name()) {
+    (Ok(d), Some(name)) => d.join(name),
+    _                   => path.to_path_buf(),
+  }
+}
@@ -0,0 +1,205 @@
+/*
+ * Settings that are kept between sessions, stored as JSON in the user's
+ * config directory.
+ */
+
+use std::fs::{ self, File };
+use std::path::PathBuf;
+use std::io::{ self, BufReader, BufWriter, Write };
+
+use crate::backend::Centroiding;
+
+use serde::{ Serialize, Deserialize };
+
+// how many recently opened files are remembered
+const MAX_RECENT: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+  pub recent:   Vec<String>,
+  pub ribbon:   Vec<RibbonAction>, // one per ribbon button, left to right
+  pub settings: Settings,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      recent: vec![],
+      ribbon: vec![
+        RibbonAction::Open,
+        RibbonAction::OpenRecent,
+        RibbonAction::Recent(0),
+        RibbonAction::SaveProject,
+        RibbonAction::Export,
+        RibbonAction::ExportPeaks,
+        RibbonAction::FindPeaks,
+        RibbonAction::ZoomToFit,
+      ],
+      settings: Settings::default(),
+    }
+  }
+}
+
+impl Config {
+
+  // A missing or unreadable file gives the defaults, so that a broken config
+  // never keeps the program from starting
+  pub fn load() -> Self {
+    let file = match path().map(File::open) {
+      Some(Ok(f)) => f,
+      _           => { return Config::default(); }
+    };
+
+    serde_json::from_reader(BufReader::new(file)).unwrap_or_default()
+  }
+
+  pub fn save(&self) -> io::Result<()> {
+    let path = match path() {
+      Some(p) => p,
+      None    => {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no config directory"));
+      }
+    };
+
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+
+    let mut out = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut out, self)?;
+    out.flush()
+  }
+
+  // moves the path to the front of the recent files
+  pub fn add_recent(&mut self, s: &String) {
+    self.recent.retain(|r| { r != s });
+    self.recent.insert(0, s.clone());
+    self.recent.truncate(MAX_RECENT);
+  }
+
+  pub fn ribbon_action(&self, button: usize) -> RibbonAction {
+    self.ribbon.get(button).copied().unwrap_or(RibbonAction::Nothing)
+  }
+}
+
+// Preferences that can be edited in the Settings popup
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+  pub colors:          Vec<String>, // of the datasets in the plot, as #rrggbb
+  pub sn_ratio:        f64,
+  pub abs_intensity:   f64,
+  pub rel_intensity:   f64,
+  pub import_dir:      String, // where the file browser starts, if set
+  pub label_precision: usize,  // decimals of m/z values in labels and lists
+  pub mass_tolerance:  f64,    // how far an annotation may be from its peak
+  pub noise_window:    f64,    // m/z width noise is estimated in, 0 for automatic
+  pub show_noise:      bool,
+  pub centroiding:     Centroiding, // how picked peaks are fitted
+}
+
+impl Default for Settings {
+  fn default() -> Self {
+    Settings {
+      colors: vec![
+        "#2b4d74".to_string(), // blue
+        "#d25466".to_string(), // red
+        "#a64185".to_string(), // purple
+      ],
+      sn_ratio:        0.0,
+      abs_intensity:   0.0,
+      rel_intensity:   0.0,
+      import_dir:      String::new(),
+      label_precision: 2,
+      mass_tolerance:  0.5,
+      noise_window:    0.0,
+      show_noise:      false,
+      centroiding:     Centroiding::default(),
+    }
+  }
+}
+
+impl Settings {
+
+  // The colour of the i-th visible dataset as (r, g, b). Colours that cannot
+  // be read are drawn black.
+  pub fn color(&self, i: usize) -> (f32, f32, f32) {
+    if self.colors.is_empty() {
+      return (0.0, 0.0, 0.0);
+    }
+    parse_color(&self.colors[i % self.colors.len()]).unwrap_or((0.0, 0.0, 0.0))
+  }
+}
+
+// reads colours written as #rrggbb
+pub fn parse_color(s: &str) -> Option<(f32, f32, f32)> {
+  let hex = s.trim().strip_prefix('#').unwrap_or(s.trim());
+  
+  if hex.len() != 6 || !hex.is_ascii() {
+    return None;
+  }
+  
+  let channel = |i: usize| -> Option<f32> {
+    u8::from_str_radix(&hex[i..i+2], 16).ok().map(|c| { c as f32 / 255.0 })
+  };
+  
+  Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+fn path() -> Option<PathBuf> {
+  dirs::config_dir().map(|dir| { dir.join("moremass").join("config.json") })
+}
+
+
+// What a ribbon button does when it is pressed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RibbonAction {
+  Nothing,
+  Open,
+  OpenRecent,
+  Recent(usize), // reopens the n-th most recent file
+  SaveProject,
+  Export,
+  ExportPeaks,
+  FindPeaks,
+  ZoomToFit,
+  ToggleNoise,
+  CloseAll,
+}
+
+impl RibbonAction {
+  pub const ALL: [RibbonAction; 13] = [
+    RibbonAction::Nothing,
+    RibbonAction::Open,
+    RibbonAction::OpenRecent,
+    RibbonAction::Recent(0),
+    RibbonAction::Recent(1),
+    RibbonAction::Recent(2),
+    RibbonAction::SaveProject,
+    RibbonAction::Export,
+    RibbonAction::ExportPeaks,
+    RibbonAction::FindPeaks,
+    RibbonAction::ZoomToFit,
+    RibbonAction::ToggleNoise,
+    RibbonAction::CloseAll,
+  ];
+}
+
+impl std::fmt::Display for RibbonAction {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RibbonAction::Nothing     => write!(f, "Nothing"),
+      RibbonAction::Open        => write!(f, "Open"),
+      RibbonAction::OpenRecent  => write!(f, "Open Recent"),
+      RibbonAction::Recent(n)   => write!(f, "Recent File {}", n + 1),
+      RibbonAction::SaveProject => write!(f, "Save Project"),
+      RibbonAction::Export      => write!(f, "Export"),
+      RibbonAction::ExportPeaks => write!(f, "Export Peak List"),
+      RibbonAction::FindPeaks   => write!(f, "Find Peaks"),
+      RibbonAction::ZoomToFit   => write!(f, "Zoom to Fit"),
+      RibbonAction::ToggleNoise => write!(f, "Show/Hide Noise"),
+      RibbonAction::CloseAll    => write!(f, "Close All"),
+    }
+  }
+}
@@ -4,7 +4,7 @@ pub mod plot;
 
 use crate::{ Message, MoreMass };
 use crate::frontend::elements::{
-  header::header, ribbon, view_datasets, 
+  header::header, ribbon, view_datasets, view_loading,
 };
 
 use iced::{
@@ -23,25 +23,29 @@ pub fn get_icon(s: &str) -> Svg {
 pub fn view<'a>(mm: &'a MoreMass) -> Element<'a, Message> {
 
   let center = column().push(
-    mm.plot.view(&mm.data)
+    mm.plot.view(&mm.data, &mm.config.settings)
   ).width(Length::FillPortion(5));
   
   
   let right: Element<Message> = mm.popup.view();
   
-  column().padding(20)
+  let main = column().padding(20)
     .align_items(Alignment::Center)
     .push(header())
-    .push(ribbon())
+    .push(ribbon(&mm.config))
     .push(
       row()
         .push( 
-          container(view_datasets(&mm.data.sets, mm.data.curr_ds))
+          container(view_datasets(&mm.data.sets, mm.data.curr_ds, &mm.config.settings))
             .width(Length::FillPortion(2))
         )
         .push(center)
         .push(right)
-    )
-    .into()
+    );
+  
+  match &mm.loading {
+    Some(loading) => main.push(view_loading(loading)).into(),
+    None          => main.into(),
+  }
 
 }
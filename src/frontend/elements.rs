@@ -6,17 +6,19 @@ pub mod header;
 pub mod popups;
 
 use crate::{
-  Message,
-  backend::{ Dataset },
+  Message, Loading,
+  backend::{ Dataset, Metadata },
+  config::{ Config, RibbonAction, Settings },
   frontend::{
     get_icon,
     plot::PlotMsg,
+    elements::header::{ WhichFileOp, WhichProcessingOp },
   },
 };
 
 use iced::{
   pure::{
-    Element, column, row, button, scrollable, text, container,
+    Element, column, row, button, scrollable, text, container, progress_bar,
     widget::{ Row }
   },
   Length, alignment, Alignment, Color
@@ -24,7 +26,8 @@ use iced::{
 
 pub fn view_datasets<'a>(
   datasets: &Vec<Dataset>,
-  selected: usize
+  selected: usize,
+  settings: &Settings,
 ) -> Element<'a, Message> {
 
   let dataset_list = 
@@ -91,6 +94,26 @@ pub fn view_datasets<'a>(
                   text("Date: ").size(12u16).width(Length::FillPortion(1))
                 ).push(
                   text(format!("{:?}", ds.metadata.date.format("%d.%m.%Y - %H:%M:%S").to_string())).size(12u16).width(Length::FillPortion(1))
+                )).push(row().padding(2).spacing(2).push(
+                  text("Path: ").size(12u16).width(Length::FillPortion(1))
+                ).push(
+                  text(format!("{}", ds.metadata.path)).size(12u16).width(Length::FillPortion(1))
+                )).push(row().padding(2).spacing(2).push(
+                  text("Source: ").size(12u16).width(Length::FillPortion(1))
+                ).push(
+                  text(format!("{}", ds.metadata.source)).size(12u16).width(Length::FillPortion(1))
+                )).push(row().padding(2).spacing(2).push(
+                  text("Processing: ").size(12u16).width(Length::FillPortion(1))
+                ).push(
+                  text(ds.metadata.processing.join("\n")).size(12u16).width(Length::FillPortion(1))
+                )).push(row().padding(2).spacing(2).push(
+                  text("Precursor: ").size(12u16).width(Length::FillPortion(1))
+                ).push(
+                  text(format_precursor(&ds.metadata)).size(12u16).width(Length::FillPortion(1))
+                )).push(row().padding(2).spacing(2).push(
+                  text("Notes: ").size(12u16).width(Length::FillPortion(1))
+                ).push(
+                  text(format!("{}", ds.metadata.notes)).size(12u16).width(Length::FillPortion(1))
                 )
               )
               
@@ -113,21 +136,48 @@ pub fn view_datasets<'a>(
           column().spacing(2).padding(10)
             .push(row().padding(2).spacing(5)
               .push(text("m/z").size(14u16).width(Length::FillPortion(1)))
-              .push(text("int").size(14u16).width(Length::FillPortion(1)))), 
-          |col, i| {
-            let pt = &datasets[selected].points[*i];
+              .push(text("int").size(14u16).width(Length::FillPortion(1)))
+              .push(text("fwhm").size(14u16).width(Length::FillPortion(1)))
+              .push(text("res.").size(14u16).width(Length::FillPortion(1)))
+              .push(text("area").size(14u16).width(Length::FillPortion(1)))
+              .push(text("label").size(14u16).width(Length::FillPortion(1)))), 
+          |col, pk| {
+            let label = datasets[selected].annotation_within(pk.index, settings.mass_tolerance)
+              .map(|a| { a.label.clone() })
+              .unwrap_or_default();
+            // peaks too narrow or too crowded to fit have no width
+            let (fwhm, resolution) = if pk.fwhm > 0.0 {
+              (format!("{:.4}", pk.fwhm), format!("{:.0}", pk.resolution()))
+            } else {
+              ("-".to_string(), "-".to_string())
+            };
             col.push(
               button(
                 row().padding(2).spacing(5)
-                  .push(text(format!("{:.2}", pt.mz)).size(12u16)
+                  .push(text(format!("{:.*}", settings.label_precision, pk.mz)).size(12u16)
+                    .width(Length::FillPortion(1))
+                    .horizontal_alignment(alignment::Horizontal::Right)
+                  )
+                  .push(text(format!("{:.0}", pk.int)).size(12u16)
+                    .width(Length::FillPortion(1))
+                    .horizontal_alignment(alignment::Horizontal::Right)
+                  )
+                  .push(text(fwhm).size(12u16)
+                    .width(Length::FillPortion(1))
+                    .horizontal_alignment(alignment::Horizontal::Right)
+                  )
+                  .push(text(resolution).size(12u16)
                     .width(Length::FillPortion(1))
                     .horizontal_alignment(alignment::Horizontal::Right)
                   )
-                  .push(text(format!("{:.0}", pt.int)).size(12u16)
+                  .push(text(format!("{:.0}", pk.area)).size(12u16)
                     .width(Length::FillPortion(1))
                     .horizontal_alignment(alignment::Horizontal::Right)
                   )
-              ).on_press(Message::ForPlot(PlotMsg::SnapTo(pt.mz as f32)))
+                  .push(text(label).size(12u16)
+                    .width(Length::FillPortion(1))
+                  )
+              ).on_press(Message::ForPlot(PlotMsg::SnapTo(pk.mz as f32)))
             )
         })
     } else {
@@ -157,42 +207,89 @@ pub fn view_datasets<'a>(
     .into()
 }
 
-pub fn ribbon<'a>() -> Row<'a, Message> {
-  row().padding(10).spacing(10)
-    .width(Length::Fill).height(Length::Units(60))
-    .push(
-      button( get_icon("outline-sun.svg") )
-        .on_press(Message::LoadFromPath("/home/sven/Documents/projects/mMass/data/20220516/Input_caColumn_1.mzXML".to_string()))
-    )
-    .push(
-      button( get_icon("outline-star-2.svg") )
-        .on_press(Message::LoadFromPath("/home/sven/Documents/projects/mMass/data/20220516/Input_caColumn_I2.mzXML".to_string()))
-    )
-    .push(
-      button( get_icon("outline-search.svg") )
-        .on_press(Message::Noop)
-    )
-    .push(
-      button( get_icon("outline-share-1.svg") )
-        .on_press(Message::Noop)
-    )
-    .push(
-      button( get_icon("outline-share-2.svg") )
-        .on_press(Message::Noop)
-    )
-    .push(
-      button( get_icon("outline-shield-check.svg") )
-        .on_press(Message::Noop)
-    )
+// A status line for the file being read in the background
+pub fn view_loading<'a>(loading: &Loading) -> Element<'a, Message> {
+
+  let status = row().padding(5).spacing(10)
+    .align_items(Alignment::Center)
+    .push(text(format!("Loading {}", loading.what)).size(14u16).width(Length::Fill))
     .push(
-      button( get_icon("outline-shirt.svg") )
-        .on_press(Message::Noop)
+      progress_bar(0.0..=1.0, loading.progress.fraction())
+        .width(Length::Units(200))
+        .height(Length::Units(10))
+    );
+  
+  if loading.progress.is_cancelled() {
+    status.push(text("Cancelling...").size(14u16)).into()
+  } else {
+    status.push(button(text("Cancel").size(14u16)).on_press(Message::CancelLoad)).into()
+  }
+}
+
+fn format_precursor(md: &Metadata) -> String {
+  let mut parts = vec![];
+  if let Some(mz) = md.precursor_mz {
+    parts.push(format!("m/z {:.4}", mz));
+  }
+  if let Some(z) = md.charge {
+    parts.push(format!("{}{}", z.abs(), if z < 0 { "-" } else { "+" }));
+  }
+  if let Some(rt) = md.retention_time {
+    parts.push(format!("RT {:.1} s", rt));
+  }
+  parts.join(", ")
+}
+
+// The icons of the ribbon buttons, left to right. What each button does is
+// up to the config.
+pub const RIBBON_ICONS: [&str; 8] = [
+  "outline-sun.svg",
+  "outline-star-2.svg",
+  "outline-search.svg",
+  "outline-share-1.svg",
+  "outline-share-2.svg",
+  "outline-shield-check.svg",
+  "outline-shirt.svg",
+  "outline-sound-2.svg",
+];
+
+pub fn ribbon<'a>(config: &Config) -> Row<'a, Message> {
+
+  RIBBON_ICONS.iter().enumerate()
+    .fold(
+      row().padding(10).spacing(10)
+        .width(Length::Fill).height(Length::Units(60)),
+      |row, (i, icon)| {
+        let btn = button( get_icon(icon) );
+        row.push(
+          match ribbon_message(config.ribbon_action(i), config) {
+            Some(msg) => btn.on_press(msg),
+            None      => btn,
+          }
+        )
+      }
     )
     .push(
-      button( get_icon("outline-sound-2.svg") )
-        .on_press(Message::Noop)
+      button( get_icon("outline-settings.svg") )
+        .on_press(Message::Popup(popups::new_settings(config)))
     )
-    .into()
+}
+
+// buttons without a message are shown disabled
+fn ribbon_message(action: RibbonAction, config: &Config) -> Option<Message> {
+  match action {
+    RibbonAction::Nothing     => None,
+    RibbonAction::Open        => Some(Message::FileOp(WhichFileOp::Open)),
+    RibbonAction::OpenRecent  => Some(Message::FileOp(WhichFileOp::OpenRecent)),
+    RibbonAction::Recent(n)   => config.recent.get(n).map(|s| { Message::LoadFromPath(s.clone()) }),
+    RibbonAction::SaveProject => Some(Message::FileOp(WhichFileOp::SaveProject)),
+    RibbonAction::Export      => Some(Message::FileOp(WhichFileOp::Export)),
+    RibbonAction::ExportPeaks => Some(Message::FileOp(WhichFileOp::ExportPeaks)),
+    RibbonAction::FindPeaks   => Some(Message::ProcessingOp(WhichProcessingOp::FindPeaks)),
+    RibbonAction::ZoomToFit   => Some(Message::ZoomToFit),
+    RibbonAction::ToggleNoise => Some(Message::ToggleNoise),
+    RibbonAction::CloseAll    => Some(Message::FileOp(WhichFileOp::CloseAll)),
+  }
 }
 
 /*
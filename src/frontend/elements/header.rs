@@ -38,13 +38,21 @@ pub fn header<'a>() -> Row<'a, Message> {
 pub enum WhichFileOp {
   New,
   Open,
+  OpenRecent,
+  SaveProject,
+  Export,
+  ExportPeaks,
   CloseAll,
 }
 
 impl WhichFileOp {
-  const ALL: [WhichFileOp; 3] = [
+  const ALL: [WhichFileOp; 7] = [
     WhichFileOp::New,
     WhichFileOp::Open,
+    WhichFileOp::OpenRecent,
+    WhichFileOp::SaveProject,
+    WhichFileOp::Export,
+    WhichFileOp::ExportPeaks,
     WhichFileOp::CloseAll,
   ];
 }
@@ -57,6 +65,10 @@ impl std::fmt::Display for WhichFileOp {
       match self {
         WhichFileOp::New => "New",
         WhichFileOp::Open => "Open",
+        WhichFileOp::OpenRecent => "Open Recent",
+        WhichFileOp::SaveProject => "Save Project",
+        WhichFileOp::Export => "Export",
+        WhichFileOp::ExportPeaks => "Export Peak List",
         WhichFileOp::CloseAll => "Close All",
       }
     )
@@ -67,11 +79,15 @@ impl std::fmt::Display for WhichFileOp {
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum WhichProcessingOp {
   FindPeaks,
+  Smoothing,
+  BaselineCorrection,
 }
 
 impl WhichProcessingOp {
-  const ALL: [WhichProcessingOp; 1] = [
+  const ALL: [WhichProcessingOp; 3] = [
     WhichProcessingOp::FindPeaks,
+    WhichProcessingOp::Smoothing,
+    WhichProcessingOp::BaselineCorrection,
   ];
 }
 
@@ -82,6 +98,8 @@ impl std::fmt::Display for WhichProcessingOp {
       "{}",
       match self {
         WhichProcessingOp::FindPeaks => "Find Peaks",
+        WhichProcessingOp::Smoothing => "Smoothing",
+        WhichProcessingOp::BaselineCorrection => "Baseline Correction",
       }
     )
   }
@@ -1,16 +1,26 @@
 
+mod baseline;
+mod customize_ribbon;
+mod export;
 mod find_file;
 mod find_peaks;
+mod recent_files;
+mod save_project;
+mod select_scan;
+mod settings;
+mod smoothing;
 
 use crate::{
   Message,
+  backend::{ Run, writer },
+  config::{ Config, RibbonAction, Settings },
   frontend::get_icon,
 };
 
 use iced::{
   Length,
   pure::{
-    Element, column, row, button, 
+    Element, column, row, button, text,
   }
 };
 
@@ -18,7 +28,16 @@ use iced::{
 pub enum WhichPopup {
   NoPopup,
   FindFile(find_file::State),
+  RecentFiles(recent_files::State),
   FindPeaks(find_peaks::State),
+  Baseline(baseline::State),
+  Smoothing(smoothing::State),
+  SelectScan(select_scan::State),
+  Export(export::State),
+  SaveProject(save_project::State),
+  CustomizeRibbon(customize_ribbon::State),
+  Settings(settings::State),
+  Error(String),
 }
 
 impl Default for WhichPopup {
@@ -31,6 +50,13 @@ impl Default for WhichPopup {
 pub enum ForPopup {
   ForFindFile(find_file::FindFileMsg),
   ForFindPeaks(find_peaks::FindPeaksMsg),
+  ForBaseline(baseline::BaselineMsg),
+  ForSmoothing(smoothing::SmoothingMsg),
+  ForSelectScan(select_scan::SelectScanMsg),
+  ForExport(export::ExportMsg),
+  ForSaveProject(save_project::SaveProjectMsg),
+  ForCustomizeRibbon(customize_ribbon::CustomizeRibbonMsg),
+  ForSettings(settings::SettingsMsg),
 }
 
 impl WhichPopup {
@@ -43,10 +69,49 @@ impl WhichPopup {
         state.view()
       }
       
+      WhichPopup::RecentFiles(state) => {
+        state.view()
+      }
+      
       WhichPopup::FindPeaks(state) => {
         state.view()
       }
       
+      WhichPopup::Baseline(state) => {
+        state.view()
+      }
+      
+      WhichPopup::Smoothing(state) => {
+        state.view()
+      }
+      
+      WhichPopup::SelectScan(state) => {
+        state.view()
+      }
+      
+      WhichPopup::Export(state) => {
+        state.view()
+      }
+      
+      WhichPopup::SaveProject(state) => {
+        state.view()
+      }
+      
+      WhichPopup::CustomizeRibbon(state) => {
+        state.view()
+      }
+      
+      WhichPopup::Settings(state) => {
+        state.view()
+      }
+      
+      WhichPopup::Error(msg) => {
+        column().padding(20).spacing(20)
+          .push(text("Error").size(20u16))
+          .push(text(msg.clone()).size(14u16))
+          .into()
+      }
+      
       _ => { return column().width(Length::Units(0)).into(); }
       
     };
@@ -86,6 +151,57 @@ impl WhichPopup {
         }
       }
       
+      WhichPopup::Baseline(state) => {
+        match msg {
+          ForPopup::ForBaseline(m) => {state.update(m);}
+          _ => {}
+        }
+      }
+      
+      WhichPopup::Smoothing(state) => {
+        match msg {
+          ForPopup::ForSmoothing(m) => {state.update(m);}
+          _ => {}
+        }
+      }
+      
+      WhichPopup::SelectScan(state) => {
+        match msg {
+          ForPopup::ForSelectScan(m) => {state.update(m);}
+          _ => {}
+        }
+      }
+      
+      WhichPopup::Export(state) => {
+        match msg {
+          ForPopup::ForExport(m) => {state.update(m);}
+          _ => {}
+        }
+      }
+      
+      WhichPopup::SaveProject(state) => {
+        match msg {
+          ForPopup::ForSaveProject(m) => {state.update(m);}
+          _ => {}
+        }
+      }
+      
+      WhichPopup::CustomizeRibbon(state) => {
+        match msg {
+          ForPopup::ForCustomizeRibbon(m) => {state.update(m);}
+          _ => {}
+        }
+      }
+      
+      WhichPopup::Settings(state) => {
+        match msg {
+          ForPopup::ForSettings(m) => {state.update(m);}
+          _ => {}
+        }
+      }
+      
+      WhichPopup::RecentFiles(_) |
+      WhichPopup::Error(_) |
       WhichPopup::NoPopup => { }
     
     }
@@ -94,11 +210,55 @@ impl WhichPopup {
 
 }
 
-pub fn new_find_file() -> WhichPopup {
-  WhichPopup::FindFile(find_file::State::default())
+// starts in the import directory from the settings, if there is one
+pub fn new_find_file(settings: &Settings) -> WhichPopup {
+  let import_dir = std::path::PathBuf::from(&settings.import_dir);
+  
+  WhichPopup::FindFile(find_file::State::new(
+    if !settings.import_dir.is_empty() && import_dir.is_dir() {
+      import_dir
+    } else {
+      std::env::current_dir().unwrap_or_default()
+    }
+  ))
+}
+
+pub fn new_recent_files(files: Vec<String>) -> WhichPopup {
+  WhichPopup::RecentFiles(recent_files::State::new(files))
+}
+
+pub fn new_find_peaks(settings: &Settings) -> WhichPopup {
+  WhichPopup::FindPeaks(find_peaks::State::new(settings))
+}
+
+pub fn new_baseline() -> WhichPopup {
+  WhichPopup::Baseline(baseline::State::default())
+}
+
+pub fn new_smoothing() -> WhichPopup {
+  WhichPopup::Smoothing(smoothing::State::default())
+}
+
+pub fn new_select_scan(run: Run) -> WhichPopup {
+  WhichPopup::SelectScan(select_scan::State::new(run))
+}
+
+pub fn new_export(format: writer::Format) -> WhichPopup {
+  WhichPopup::Export(export::State::new(format))
 }
 
-pub fn new_find_peaks() -> WhichPopup {
-  WhichPopup::FindPeaks(find_peaks::State::default())
+pub fn new_save_project() -> WhichPopup {
+  WhichPopup::SaveProject(save_project::State::default())
 }
 
+pub fn new_customize_ribbon(actions: Vec<RibbonAction>) -> WhichPopup {
+  WhichPopup::CustomizeRibbon(customize_ribbon::State::new(actions))
+}
+
+pub fn new_settings(config: &Config) -> WhichPopup {
+  WhichPopup::Settings(settings::State::new(config))
+}
+
+pub fn new_error(msg: String) -> WhichPopup {
+  WhichPopup::Error(msg)
+}
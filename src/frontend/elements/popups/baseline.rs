@@ -0,0 +1,131 @@
+use crate::Message;
+use crate::backend::baseline::{ Baseline, Method };
+use crate::frontend::elements::popups::ForPopup;
+
+use std::str::FromStr;
+
+use iced::{
+  Length,
+
+  pure::{
+    Element, column, text, row, text_input, button, pick_list,
+  }
+};
+
+#[derive(Debug, Clone)]
+pub enum BaselineMsg {
+  Method(Method),
+  Width(String),
+  Degree(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+  method: Method,
+  width:  String,
+  degree: String,
+}
+
+impl Default for State {
+  fn default() -> Self {
+    let baseline = Baseline::default();
+    State {
+      method: baseline.method,
+      width:  baseline.width.to_string(),
+      degree: baseline.degree.to_string(),
+    }
+  }
+}
+
+impl State {
+
+  pub fn view<'a>(&self) -> Element<'a, Message> {
+
+    let mut form = column().spacing(10)
+      .push(
+        row()
+          .push(text("Method").width(Length::FillPortion(1)))
+          .push(
+            pick_list(
+              &Method::ALL[..],
+              Some(self.method),
+              |m| { Message::ForPopup(ForPopup::ForBaseline(BaselineMsg::Method(m))) }
+            ).width(Length::FillPortion(1))
+          )
+      )
+      .push(
+        row()
+          .push(text("Width (points)").width(Length::FillPortion(1)))
+          .push(
+            text_input(
+              "",
+              &self.width,
+              |s| { Message::ForPopup(ForPopup::ForBaseline(BaselineMsg::Width(s))) }
+            ).width(Length::FillPortion(1))
+          )
+      );
+
+    if self.method == Method::Polynomial {
+      form = form.push(
+        row()
+          .push(text("Degree").width(Length::FillPortion(1)))
+          .push(
+            text_input(
+              "",
+              &self.degree,
+              |s| { Message::ForPopup(ForPopup::ForBaseline(BaselineMsg::Degree(s))) }
+            ).width(Length::FillPortion(1))
+          )
+      );
+    }
+
+    let (preview, subtract) = match self.parse_inputs() {
+      Some(b) => (
+        button(text("Preview")).on_press(Message::PreviewBaseline(b)),
+        button(text("Subtract")).on_press(Message::SubtractBaseline(b)),
+      ),
+      None => (
+        button(text("Preview")),
+        button(text("Subtract")),
+      ),
+    };
+
+    column().padding(20).spacing(20)
+      .push(text("Baseline Correction").size(20u16))
+      .push(form)
+      .push(row().spacing(10).push(preview).push(subtract))
+      .into()
+  }
+
+  pub fn update(&mut self, msg: BaselineMsg) {
+    match msg {
+      BaselineMsg::Method(m) => {
+        self.method = m;
+      }
+      BaselineMsg::Width(s) => {
+        self.width = s;
+      }
+      BaselineMsg::Degree(s) => {
+        self.degree = s;
+      }
+    }
+  }
+
+  // None while an input is not a usable number
+  fn parse_inputs(&self) -> Option<Baseline> {
+    let width  = usize::from_str(self.width.trim()).ok().filter(|w| { *w > 0 })?;
+    let degree = usize::from_str(self.degree.trim()).ok().filter(|d| { *d <= 6 })?;
+
+    // a segment needs degree + 1 points to fit the polynomial to
+    if self.method == Method::Polynomial && width < degree + 1 {
+      return None;
+    }
+
+    Some(Baseline {
+      method: self.method,
+      width,
+      degree,
+    })
+  }
+
+}
@@ -0,0 +1,72 @@
+use crate::Message;
+use crate::config::RibbonAction;
+use crate::frontend::{
+  get_icon,
+  elements::{ RIBBON_ICONS, popups::ForPopup },
+};
+
+use iced::{
+  Length, Alignment,
+
+  pure::{
+    Element, column, text, row, button, pick_list, container,
+  }
+};
+
+#[derive(Debug, Clone)]
+pub enum CustomizeRibbonMsg {
+  Assign(usize, RibbonAction),
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+  actions: Vec<RibbonAction>,
+}
+
+impl State {
+
+  pub fn new(mut actions: Vec<RibbonAction>) -> Self {
+    actions.resize(RIBBON_ICONS.len(), RibbonAction::Nothing);
+    State { actions }
+  }
+
+  pub fn view<'a>(&self) -> Element<'a, Message> {
+
+    let buttons = RIBBON_ICONS.iter().enumerate()
+      .fold(column().spacing(5).padding(0), |col, (i, icon)| {
+        col.push(
+          row().spacing(10)
+            .align_items(Alignment::Center)
+            .push(container(get_icon(icon)).width(Length::Units(30)))
+            .push(
+              pick_list(
+                &RibbonAction::ALL[..],
+                Some(self.actions[i]),
+                move |action| {
+                  Message::ForPopup(
+                    ForPopup::ForCustomizeRibbon(
+                      CustomizeRibbonMsg::Assign(i, action))) }
+              ).width(Length::Fill)
+            )
+        )
+      });
+
+    column().padding(20).spacing(20)
+      .push(text("Ribbon Buttons").size(20u16))
+      .push(buttons)
+      .push(
+        button(text("Save")).on_press(Message::SetRibbon(self.actions.clone()))
+      ).into()
+  }
+
+  pub fn update(&mut self, msg: CustomizeRibbonMsg) {
+    match msg {
+      CustomizeRibbonMsg::Assign(i, action) => {
+        if i < self.actions.len() {
+          self.actions[i] = action;
+        }
+      }
+    }
+  }
+
+}
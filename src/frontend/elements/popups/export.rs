@@ -0,0 +1,127 @@
+
+use crate::Message;
+use crate::backend::writer::{ Format, Encoding };
+use crate::frontend::elements::popups::ForPopup;
+
+use iced::{
+  Length,
+
+  pure::{
+    Element, column, text, row, text_input, button, pick_list, checkbox,
+  }
+};
+
+#[derive(Debug, Clone)]
+pub enum ExportMsg {
+  PathInput(String),
+  FormatSelected(Format),
+  ToggleDouble(bool),
+  ToggleZlib(bool),
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+  file_path: String,
+  format:    Format,
+  encoding:  Encoding,
+}
+
+impl State {
+
+  pub fn new(format: Format) -> Self {
+    State {
+      file_path: String::new(),
+      format,
+      encoding:  Encoding::default(),
+    }
+  }
+
+  pub fn view<'a>(&self) -> Element<'a, Message> {
+
+    let col = column().padding(20).spacing(20)
+      .push(text("Export Dataset").size(20u16))
+      .push(
+        row()
+          .push(text("Format").width(Length::FillPortion(1)))
+          .push(
+            pick_list(
+              &Format::ALL[..],
+              Some(self.format),
+              |f| {
+                Message::ForPopup(
+                  ForPopup::ForExport(
+                    ExportMsg::FormatSelected(f))) }
+            )
+            .width(Length::FillPortion(1))
+          )
+      );
+
+    // only mzXML and mzML store binary arrays
+    let col = if self.format.is_binary() {
+      col.push(
+        checkbox(
+          "64-bit floats",
+          self.encoding.double,
+          |b| {
+            Message::ForPopup(
+              ForPopup::ForExport(
+                ExportMsg::ToggleDouble(b))) }
+        )
+      ).push(
+        checkbox(
+          "zlib compression",
+          self.encoding.zlib,
+          |b| {
+            Message::ForPopup(
+              ForPopup::ForExport(
+                ExportMsg::ToggleZlib(b))) }
+        )
+      )
+    } else {
+      col
+    };
+
+    col
+      .push(
+        row()
+          .push(text("File Path").width(Length::FillPortion(1)))
+          .push(
+            text_input(
+              &format!("spectrum.{}", self.format.extension()),
+              &self.file_path,
+              |s| {
+                Message::ForPopup(
+                  ForPopup::ForExport(
+                    ExportMsg::PathInput(s))) }
+            )
+            .on_submit(self.submit())
+            .width(Length::FillPortion(1))
+          )
+      ).push(
+        button(text("Save")).on_press(self.submit())
+      ).into()
+
+  }
+
+  pub fn update(&mut self, msg: ExportMsg) {
+    match msg {
+      ExportMsg::PathInput(s) => {
+        self.file_path = s;
+      }
+      ExportMsg::FormatSelected(f) => {
+        self.format = f;
+      }
+      ExportMsg::ToggleDouble(b) => {
+        self.encoding.double = b;
+      }
+      ExportMsg::ToggleZlib(b) => {
+        self.encoding.zlib = b;
+      }
+    }
+  }
+
+  fn submit(&self) -> Message {
+    Message::Export(self.format, self.encoding, self.file_path.clone())
+  }
+
+}
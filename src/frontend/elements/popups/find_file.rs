@@ -1,48 +1,268 @@
 
 use crate::Message;
-
+use crate::backend::{ parser, project };
 use crate::frontend::elements::popups::ForPopup;
 
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{ DateTime, Local };
 use iced::{
+  Length,
   alignment::Horizontal,
   
   pure::{
-    Element, column, text, text_input, 
+    Element, column, row, text, text_input, button, checkbox, scrollable,
   }
 };
 
 #[derive(Debug, Clone)]
 pub enum FindFileMsg {
   PathInput(String),
+  PathSubmit,
+  Enter(PathBuf),
+  Up,
+  Toggle(PathBuf),
+  ShowAll(bool),
+}
+
+// A file or directory in the current directory
+#[derive(Debug, Clone)]
+struct Entry {
+  path:     PathBuf,
+  name:     String,
+  is_dir:   bool,
+  size:     u64,
+  modified: Option<DateTime<Local>>,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct State {
-  file_path: String,
+  dir:        PathBuf,
+  path_input: String,
+  entries:    Vec<Entry>,
+  selected:   Vec<PathBuf>,
+  show_all:   bool,
+  error:      Option<String>,
 }
 
 impl State {
 
+  pub fn new(dir: PathBuf) -> Self {
+    let mut state = State {
+      dir:        PathBuf::new(),
+      path_input: String::new(),
+      entries:    vec![],
+      selected:   vec![],
+      show_all:   false,
+      error:      None,
+    };
+    state.enter(dir);
+    state
+  }
+
   pub fn view<'a>(&self) -> Element<'a, Message> {
 
+    let file_list = self.entries.iter()
+      .fold(
+        column().spacing(2).padding(0)
+          .push(row().padding(2).spacing(5)
+            .push(text("Name"    ).size(14u16).width(Length::FillPortion(4)))
+            .push(text("Size"    ).size(14u16).width(Length::FillPortion(1)))
+            .push(text("Modified").size(14u16).width(Length::FillPortion(2)))),
+        |col, entry| {
+          let name: Element<'a, Message> = if entry.is_dir {
+            button(text(format!("{}/", entry.name)).size(12u16))
+              .padding(2)
+              .on_press(Message::ForPopup(
+                ForPopup::ForFindFile(
+                  FindFileMsg::Enter(entry.path.clone()))))
+              .into()
+          } else {
+            let path = entry.path.clone();
+            checkbox(
+              entry.name.clone(),
+              self.selected.contains(&entry.path),
+              move |_| {
+                Message::ForPopup(
+                  ForPopup::ForFindFile(
+                    FindFileMsg::Toggle(path.clone()))) }
+            )
+            .size(12u16)
+            .text_size(12u16)
+            .into()
+          };
+          
+          col.push(
+            row().padding(2).spacing(5)
+              .push(column().push(name).width(Length::FillPortion(4)))
+              .push(text(if entry.is_dir { String::new() } else { format_size(entry.size) })
+                .size(12u16)
+                .width(Length::FillPortion(1))
+                .horizontal_alignment(Horizontal::Right))
+              .push(text(entry.modified
+                  .map(|date| { date.format("%d.%m.%Y %H:%M").to_string() })
+                  .unwrap_or_default())
+                .size(12u16)
+                .width(Length::FillPortion(2))
+                .horizontal_alignment(Horizontal::Right))
+          )
+      });
+    
+    let file_list = match &self.error {
+      Some(why) => file_list.push(text(why.clone()).size(12u16)),
+      None      => file_list,
+    };
+    
+    let open = button(text("Open"));
+    let open = if self.selected.is_empty() {
+      open
+    } else {
+      open.on_press(Message::LoadFromPaths(
+        self.selected.iter()
+          .map(|path| { path.to_string_lossy().to_string() })
+          .collect()
+      ))
+    };
+
     column().padding(20).spacing(20)
-      .align_items(Horizontal::Center.into())
-      .push(text("Enter File Path:"))
+      .push(text("Open Files").size(20u16))
+      .push(
+        row().spacing(5)
+          .push(
+            button(text("Up"))
+              .on_press(Message::ForPopup(ForPopup::ForFindFile(FindFileMsg::Up)))
+          )
+          .push(
+            text_input(
+              "Directory or File Path",
+              &self.path_input,
+              |s| { Message::ForPopup(ForPopup::ForFindFile(FindFileMsg::PathInput(s))) },
+            ).on_submit(Message::ForPopup(ForPopup::ForFindFile(FindFileMsg::PathSubmit)))
+          )
+      )
+      .push(scrollable(file_list).height(Length::Units(300)))
       .push(
-        text_input(
-          "File Path",
-          &self.file_path,
-          |s| { Message::ForPopup(ForPopup::ForFindFile(FindFileMsg::PathInput(s)))},
-        ).on_submit(Message::LoadFromPath(self.file_path.clone()))
+        checkbox(
+          "Show all files",
+          self.show_all,
+          |b| { Message::ForPopup(ForPopup::ForFindFile(FindFileMsg::ShowAll(b))) }
+        )
+      )
+      .push(
+        row().spacing(10)
+          .push(text(format!("{} selected", self.selected.len())).size(14u16))
+          .push(open)
       ).into()
   }
   
   pub fn update(&mut self, msg: FindFileMsg) {
     match msg {
       FindFileMsg::PathInput(s) => {
-        self.file_path = s;
+        self.path_input = s;
+      }
+      // typed paths may lead to a directory or straight to a file
+      FindFileMsg::PathSubmit => {
+        let path = PathBuf::from(&self.path_input);
+        if path.is_dir() {
+          self.enter(path);
+        } else if path.is_file() {
+          if !self.selected.contains(&path) {
+            self.selected.push(path);
+          }
+        } else {
+          self.error = Some(format!("{} does not exist", self.path_input));
+        }
+      }
+      FindFileMsg::Enter(path) => {
+        self.enter(path);
+      }
+      FindFileMsg::Up => {
+        if let Some(parent) = self.dir.parent() {
+          self.enter(parent.to_path_buf());
+        }
+      }
+      FindFileMsg::Toggle(path) => {
+        if self.selected.contains(&path) {
+          self.selected.retain(|p| { *p != path });
+        } else {
+          self.selected.push(path);
+        }
+      }
+      FindFileMsg::ShowAll(b) => {
+        self.show_all = b;
+        self.enter(self.dir.clone());
       }
     }
   }
+  
+  // lists a directory: subdirectories first, then the files that can be read
+  // (or all of them), each sorted by name. The selection is kept, so files
+  // from several directories can be opened at once.
+  fn enter(&mut self, dir: PathBuf) {
+  
+    let read = match fs::read_dir(&dir) {
+      Err(why) => {
+        self.error = Some(format!("Could not open {}: {}", dir.to_string_lossy(), why));
+        return;
+      }
+      Ok(read) => read
+    };
+    
+    let mut entries: Vec<Entry> = read
+      .filter_map(|maybe_entry| { maybe_entry.ok() })
+      .filter_map(|entry| {
+        let path     = entry.path();
+        let metadata = fs::metadata(&path).ok()?;
+        Some(Entry {
+          name:     entry.file_name().to_string_lossy().to_string(),
+          is_dir:   metadata.is_dir(),
+          size:     metadata.len(),
+          modified: metadata.modified().ok().map(|time| { DateTime::<Local>::from(time) }),
+          path:     path,
+        })
+      })
+      .filter(|entry| {
+        self.show_all || (!entry.name.starts_with('.') && (entry.is_dir || is_supported(entry)))
+      })
+      .collect();
+    
+    entries.sort_by(|a, b| {
+      b.is_dir.cmp(&a.is_dir)
+        .then(a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+    
+    self.path_input = dir.to_string_lossy().to_string();
+    self.dir        = dir;
+    self.entries    = entries;
+    self.error      = None;
+  }
+
+}
 
+fn is_supported(entry: &Entry) -> bool {
+  match entry.path.extension() {
+    None      => false,
+    Some(ext) => {
+      let ext = ext.to_string_lossy().to_lowercase();
+      parser::EXTENSIONS.contains(&ext.as_str()) || ext == project::EXTENSION
+    }
+  }
+}
+
+fn format_size(bytes: u64) -> String {
+  let units = ["B", "kB", "MB", "GB", "TB"];
+  let mut size = bytes as f64;
+  let mut unit = 0;
+  
+  while size >= 1000.0 && unit < units.len() - 1 {
+    size /= 1000.0;
+    unit += 1;
+  }
+  
+  if unit == 0 {
+    format!("{} {}", bytes, units[0])
+  } else {
+    format!("{:.1} {}", size, units[unit])
+  }
 }
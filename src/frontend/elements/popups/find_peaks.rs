@@ -1,38 +1,118 @@
 
 use crate::Message;
+use crate::backend::cwt::{ Cwt, MIN_SCALE };
+use crate::config::Settings;
 use crate::frontend::elements::popups::ForPopup;
 
 use std::str::FromStr;
 
 use iced::{
-  Length, 
+  Color, Length, 
   
   pure::{
-    Element, column, text, row, text_input, button, 
+    Element, column, text, row, text_input, button, pick_list,
   }
 };
 
+// how the candidates for peaks are found
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Algorithm {
+  LocalMaxima,
+  Wavelet,
+}
+
+impl Algorithm {
+  pub const ALL: [Algorithm; 2] = [
+    Algorithm::LocalMaxima,
+    Algorithm::Wavelet,
+  ];
+}
+
+impl Default for Algorithm {
+  fn default() -> Self {
+    Algorithm::LocalMaxima
+  }
+}
+
+impl std::fmt::Display for Algorithm {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{}",
+      match self {
+        Algorithm::LocalMaxima => "Local Maxima",
+        Algorithm::Wavelet     => "Wavelet Transform",
+      }
+    )
+  }
+}
+
 #[derive(Debug, Clone)]
 pub enum FindPeaksMsg {
+  Algorithm(Algorithm),
   SNRatio(String),
   AbsIntensity(String),
   RelIntensity(String),
+  MinScale(String),
+  MaxScale(String),
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct State {
+  algorithm: Algorithm,
   sn_ratio: String,
   abs_intensity: String,
   rel_intensity: String,
+  min_scale: String, // of the wavelets, in points
+  max_scale: String,
 }
 
 impl State {
 
+  // starts out with the thresholds from the settings
+  pub fn new(settings: &Settings) -> Self {
+    let cwt = Cwt::default();
+    State {
+      algorithm:     Algorithm::default(),
+      sn_ratio:      settings.sn_ratio.to_string(),
+      abs_intensity: settings.abs_intensity.to_string(),
+      rel_intensity: settings.rel_intensity.to_string(),
+      min_scale:     cwt.min_scale.to_string(),
+      max_scale:     cwt.max_scale.to_string(),
+    }
+  }
+
   pub fn view<'a>(&self) -> Element<'a, Message> {
   
-    column().padding(20).spacing(20)
+    let (status, go, submit) = match self.parse_inputs() {
+      Err(why) => (
+        text(why).size(12u16).color(Color { r: 0.8, g: 0.0, b: 0.0, a: 1.0 }),
+        button(text("Go")),
+        Message::Noop,
+      ),
+      Ok(msg) => (
+        text("").size(12u16),
+        button(text("Go")).on_press(msg.clone()),
+        msg,
+      ),
+    };
+    
+    let mut col = column().padding(20).spacing(20)
       .push(text("Find Peaks").size(20u16))
       .push(
+        row()
+          .push(text("Algorithm").width(Length::FillPortion(1)))
+          .push(
+            pick_list(
+              &Algorithm::ALL[..],
+              Some(self.algorithm),
+              |a| {
+                Message::ForPopup(
+                  ForPopup::ForFindPeaks(
+                    FindPeaksMsg::Algorithm(a))) }
+            ).width(Length::FillPortion(1))
+          )
+      ).push(
         row()
           .push(text("S/N-Ratio").width(Length::FillPortion(1)))
           .push(
@@ -44,7 +124,7 @@ impl State {
                   ForPopup::ForFindPeaks(
                     FindPeaksMsg::SNRatio(s))) }
             )
-            .on_submit(self.parse_inputs())
+            .on_submit(submit.clone())
             .width(Length::FillPortion(1))
           )
       ).push(
@@ -59,7 +139,7 @@ impl State {
                   ForPopup::ForFindPeaks(
                     FindPeaksMsg::AbsIntensity(s))) },
             )
-            .on_submit(self.parse_inputs())
+            .on_submit(submit.clone())
             .width(Length::FillPortion(1))
           )
       ).push(
@@ -74,17 +154,55 @@ impl State {
                   ForPopup::ForFindPeaks(
                     FindPeaksMsg::RelIntensity(s)))}
             )
-            .on_submit(self.parse_inputs())
+            .on_submit(submit.clone())
+            .width(Length::FillPortion(1))
+          )
+      );
+    
+    // the widths of the wavelets only matter to the wavelet transform
+    if self.algorithm == Algorithm::Wavelet {
+      col = col.push(
+        row()
+          .push(text("Min. Scale (points)").width(Length::FillPortion(1)))
+          .push(
+            text_input(
+              "",
+              &self.min_scale,
+              |s| {
+                Message::ForPopup(
+                  ForPopup::ForFindPeaks(
+                    FindPeaksMsg::MinScale(s)))}
+            )
+            .on_submit(submit.clone())
             .width(Length::FillPortion(1))
           )
       ).push(
-        button(text("Go")).on_press(self.parse_inputs())
-      ).into()
+        row()
+          .push(text("Max. Scale (points)").width(Length::FillPortion(1)))
+          .push(
+            text_input(
+              "",
+              &self.max_scale,
+              |s| {
+                Message::ForPopup(
+                  ForPopup::ForFindPeaks(
+                    FindPeaksMsg::MaxScale(s)))}
+            )
+            .on_submit(submit.clone())
+            .width(Length::FillPortion(1))
+          )
+      );
+    }
+    
+    col.push(status).push(go).into()
     
   }
   
   pub fn update(&mut self, msg: FindPeaksMsg) {
     match msg {
+      FindPeaksMsg::Algorithm(a) => {
+        self.algorithm = a;
+      }
       FindPeaksMsg::SNRatio(s) => {
         self.sn_ratio = s;
       }
@@ -94,10 +212,16 @@ impl State {
       FindPeaksMsg::RelIntensity(s) => {
         self.rel_intensity = s;
       }
+      FindPeaksMsg::MinScale(s) => {
+        self.min_scale = s;
+      }
+      FindPeaksMsg::MaxScale(s) => {
+        self.max_scale = s;
+      }
     }
   }
   
-  pub fn parse_inputs(&self) -> Message {
+  pub fn parse_inputs(&self) -> Result<Message, String> {
     
     let ratio   = if let Ok(v) = f64::from_str(&self.sn_ratio) {
       v
@@ -115,8 +239,28 @@ impl State {
       0.0
     };
     
-    Message::FindPeaks(ratio, abs_int, rel_int)
+    let cwt = match self.algorithm {
+      Algorithm::LocalMaxima => None,
+      Algorithm::Wavelet     => Some(self.parse_scales()?),
+    };
+    
+    Ok(Message::FindPeaks(ratio, abs_int, rel_int, cwt))
+    
+  }
+  
+  // the smallest scale has to be sampled by the points, and the largest
+  // cannot be smaller than it
+  fn parse_scales(&self) -> Result<Cwt, String> {
+    let min_scale = match f64::from_str(self.min_scale.trim()) {
+      Ok(v) if v.is_finite() && v >= MIN_SCALE => v,
+      _ => { return Err(format!("Min. scale must be a number of at least {}", MIN_SCALE)); }
+    };
+    let max_scale = match f64::from_str(self.max_scale.trim()) {
+      Ok(v) if v.is_finite() && v >= min_scale => v,
+      _ => { return Err("Max. scale must be a number no smaller than Min. scale".to_string()); }
+    };
     
+    Ok(Cwt { min_scale, max_scale })
   }
 
 }
@@ -0,0 +1,57 @@
+use crate::Message;
+
+use std::path::Path;
+
+use iced::{
+  Length,
+
+  pure::{
+    Element, column, text, button, scrollable,
+  }
+};
+
+#[derive(Default, Debug, Clone)]
+pub struct State {
+  files: Vec<String>,
+}
+
+impl State {
+
+  pub fn new(files: Vec<String>) -> Self {
+    State { files }
+  }
+
+  pub fn view<'a>(&self) -> Element<'a, Message> {
+
+    if self.files.is_empty() {
+      return column().padding(20).spacing(20)
+        .push(text("Open Recent").size(20u16))
+        .push(text("No files have been opened yet").size(14u16))
+        .into();
+    }
+
+    let file_list = self.files.iter()
+      .fold(column().spacing(2).padding(0), |col, s| {
+        let name = Path::new(s).file_name()
+          .map(|n| { n.to_string_lossy().to_string() })
+          .unwrap_or_else(|| { s.clone() });
+
+        col.push(
+          button(
+            column().padding(2).spacing(0)
+              .push(text(name).size(14u16))
+              .push(text(s.clone()).size(10u16))
+          )
+          .width(Length::Fill)
+          .on_press(Message::LoadFromPath(s.clone()))
+        )
+      });
+
+    column().padding(20).spacing(20)
+      .push(text("Open Recent").size(20u16))
+      .push(scrollable(file_list).height(Length::Units(300)))
+      .push(button(text("Clear List")).on_press(Message::ClearRecent))
+      .into()
+  }
+
+}
@@ -0,0 +1,60 @@
+
+use crate::Message;
+use crate::backend::project::EXTENSION;
+use crate::frontend::elements::popups::ForPopup;
+
+use iced::{
+  alignment::Horizontal,
+
+  pure::{
+    Element, column, text, text_input, button,
+  }
+};
+
+#[derive(Debug, Clone)]
+pub enum SaveProjectMsg {
+  PathInput(String),
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct State {
+  file_path: String,
+}
+
+impl State {
+
+  pub fn view<'a>(&self) -> Element<'a, Message> {
+
+    column().padding(20).spacing(20)
+      .align_items(Horizontal::Center.into())
+      .push(text("Save Project As:"))
+      .push(
+        text_input(
+          &format!("analysis.{}", EXTENSION),
+          &self.file_path,
+          |s| { Message::ForPopup(ForPopup::ForSaveProject(SaveProjectMsg::PathInput(s)))},
+        ).on_submit(Message::SaveProject(self.with_extension()))
+      )
+      .push(
+        button(text("Save")).on_press(Message::SaveProject(self.with_extension()))
+      ).into()
+  }
+
+  pub fn update(&mut self, msg: SaveProjectMsg) {
+    match msg {
+      SaveProjectMsg::PathInput(s) => {
+        self.file_path = s;
+      }
+    }
+  }
+
+  // projects are recognised by their extension when they are opened again
+  fn with_extension(&self) -> String {
+    if self.file_path.to_lowercase().ends_with(&format!(".{}", EXTENSION)) {
+      self.file_path.clone()
+    } else {
+      format!("{}.{}", self.file_path, EXTENSION)
+    }
+  }
+
+}
@@ -0,0 +1,157 @@
+
+use crate::Message;
+use crate::backend::Run;
+use crate::frontend::elements::popups::ForPopup;
+
+use std::str::FromStr;
+
+use iced::{
+  Length,
+  alignment::Horizontal,
+
+  pure::{
+    Element, column, text, row, text_input, button, scrollable,
+  }
+};
+
+#[derive(Debug, Clone)]
+pub enum SelectScanMsg {
+  From(String),
+  To(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+  pub run: Run,
+  from: String,
+  to:   String,
+}
+
+impl State {
+
+  pub fn new(run: Run) -> Self {
+    State {
+      run,
+      from: String::new(),
+      to:   String::new(),
+    }
+  }
+
+  pub fn view<'a>(&self) -> Element<'a, Message> {
+
+    let scan_list = self.run.scans.iter()
+      .fold(
+        column().spacing(2).padding(0)
+          .push(row().padding(2).spacing(5)
+            .push(text("#"   ).size(14u16).width(Length::FillPortion(1)))
+            .push(text("MS"  ).size(14u16).width(Length::FillPortion(1)))
+            .push(text("RT"  ).size(14u16).width(Length::FillPortion(2)))
+            .push(text("Pol.").size(14u16).width(Length::FillPortion(1)))
+            .push(text("Base").size(14u16).width(Length::FillPortion(2)))
+            .push(text("TIC" ).size(14u16).width(Length::FillPortion(2)))),
+        |col, scan| {
+          col.push(
+            button(
+              row().padding(2).spacing(5)
+                .push(text(format!("{}", scan.num)).size(12u16)
+                  .width(Length::FillPortion(1)))
+                .push(text(format!("{}", scan.ms_level)).size(12u16)
+                  .width(Length::FillPortion(1)))
+                .push(text(format_opt(scan.retention_time.map(|rt| {rt / 60.0}), 2)).size(12u16)
+                  .width(Length::FillPortion(2))
+                  .horizontal_alignment(Horizontal::Right))
+                .push(text(scan.polarity.clone()).size(12u16)
+                  .width(Length::FillPortion(1)))
+                .push(text(format_opt(scan.base_peak_mz, 2)).size(12u16)
+                  .width(Length::FillPortion(2))
+                  .horizontal_alignment(Horizontal::Right))
+                .push(text(format_opt(scan.tot_current, 0)).size(12u16)
+                  .width(Length::FillPortion(2))
+                  .horizontal_alignment(Horizontal::Right))
+            ).on_press(Message::LoadScans(scan.num, scan.num))
+          )
+      });
+
+    column().padding(20).spacing(20)
+      .push(text("Select Scan").size(20u16))
+      .push(text(format!("{} scans, retention times in minutes", self.run.scans.len())).size(12u16))
+      .push(scrollable(scan_list).height(Length::Units(300)))
+      .push(text("Sum a range of scans:").size(14u16))
+      .push(
+        row()
+          .push(text("From").width(Length::FillPortion(1)))
+          .push(
+            text_input(
+              "",
+              &self.from,
+              |s| {
+                Message::ForPopup(
+                  ForPopup::ForSelectScan(
+                    SelectScanMsg::From(s))) }
+            )
+            .on_submit(self.parse_inputs())
+            .width(Length::FillPortion(1))
+          )
+      ).push(
+        row()
+          .push(text("To").width(Length::FillPortion(1)))
+          .push(
+            text_input(
+              "",
+              &self.to,
+              |s| {
+                Message::ForPopup(
+                  ForPopup::ForSelectScan(
+                    SelectScanMsg::To(s))) }
+            )
+            .on_submit(self.parse_inputs())
+            .width(Length::FillPortion(1))
+          )
+      ).push(
+        row().spacing(10)
+          .push(button(text("Sum")).on_press(self.parse_inputs()))
+          .push(button(text("Load Each")).on_press(Message::LoadAllScans))
+      ).into()
+
+  }
+
+  pub fn update(&mut self, msg: SelectScanMsg) {
+    match msg {
+      SelectScanMsg::From(s) => {
+        self.from = s;
+      }
+      SelectScanMsg::To(s) => {
+        self.to = s;
+      }
+    }
+  }
+
+  // empty fields default to the first and last scan respectively
+  pub fn parse_inputs(&self) -> Message {
+
+    let first = self.run.scans.first().map(|scan| {scan.num}).unwrap_or(0);
+    let last  = self.run.scans.last().map(|scan| {scan.num}).unwrap_or(0);
+
+    let from = if let Ok(v) = u32::from_str(&self.from) {
+      v
+    } else {
+      first
+    };
+    let to = if let Ok(v) = u32::from_str(&self.to) {
+      v
+    } else {
+      last
+    };
+
+    Message::LoadScans(from, to)
+
+  }
+
+}
+
+fn format_opt(val: Option<f64>, precision: usize) -> String {
+  match val {
+    Some(v) => format!("{:.*}", precision, v),
+    None    => "-".to_string(),
+  }
+}
@@ -0,0 +1,198 @@
+use crate::Message;
+use crate::backend::Centroiding;
+use crate::config::{ Config, RibbonAction, Settings, parse_color };
+use crate::frontend::elements::popups::{ self, ForPopup };
+
+use std::str::FromStr;
+
+use iced::{
+  Length, Color,
+
+  pure::{
+    Element, column, text, row, text_input, button, checkbox, pick_list,
+  }
+};
+
+#[derive(Debug, Clone)]
+pub enum SettingsMsg {
+  Color(usize, String),
+  SNRatio(String),
+  AbsIntensity(String),
+  RelIntensity(String),
+  ImportDir(String),
+  LabelPrecision(String),
+  MassTolerance(String),
+  NoiseWindow(String),
+  ShowNoise(bool),
+  Centroiding(Centroiding),
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+  colors:          Vec<String>,
+  sn_ratio:        String,
+  abs_intensity:   String,
+  rel_intensity:   String,
+  import_dir:      String,
+  label_precision: String,
+  mass_tolerance:  String,
+  noise_window:    String,
+  show_noise:      bool,
+  centroiding:     Centroiding,
+  ribbon:          Vec<RibbonAction>, // handed on to the ribbon popup
+}
+
+impl State {
+
+  pub fn new(config: &Config) -> Self {
+    let settings = &config.settings;
+    State {
+      colors:          settings.colors.clone(),
+      sn_ratio:        settings.sn_ratio.to_string(),
+      abs_intensity:   settings.abs_intensity.to_string(),
+      rel_intensity:   settings.rel_intensity.to_string(),
+      import_dir:      settings.import_dir.clone(),
+      label_precision: settings.label_precision.to_string(),
+      mass_tolerance:  settings.mass_tolerance.to_string(),
+      noise_window:    settings.noise_window.to_string(),
+      show_noise:      settings.show_noise,
+      centroiding:     settings.centroiding,
+      ribbon:          config.ribbon.clone(),
+    }
+  }
+
+  pub fn view<'a>(&self) -> Element<'a, Message> {
+
+    let colors = self.colors.iter().enumerate()
+      .fold(row().spacing(5), |row, (i, c)| {
+        row.push(
+          text_input("#rrggbb", c, move |s| { to_msg(SettingsMsg::Color(i, s)) })
+            .width(Length::FillPortion(1))
+        )
+      });
+
+    let form = column().spacing(10)
+      .push(
+        row()
+          .push(text("Plot Colours").width(Length::FillPortion(1)))
+          .push(colors.width(Length::FillPortion(1)))
+      )
+      .push(self.field("S/N-Ratio", &self.sn_ratio, SettingsMsg::SNRatio))
+      .push(self.field("Abs. Intensity", &self.abs_intensity, SettingsMsg::AbsIntensity))
+      .push(self.field("Rel. Intensity", &self.rel_intensity, SettingsMsg::RelIntensity))
+      .push(self.field("Import Directory", &self.import_dir, SettingsMsg::ImportDir))
+      .push(self.field("Label Decimals", &self.label_precision, SettingsMsg::LabelPrecision))
+      .push(self.field("Mass Tolerance", &self.mass_tolerance, SettingsMsg::MassTolerance))
+      .push(self.field("Noise Window (m/z, 0 = auto)", &self.noise_window, SettingsMsg::NoiseWindow))
+      .push(
+        checkbox("Show noise band", self.show_noise, |b| { to_msg(SettingsMsg::ShowNoise(b)) })
+      )
+      .push(
+        row()
+          .push(text("Peak Apex").width(Length::FillPortion(1)))
+          .push(
+            pick_list(
+              &Centroiding::ALL[..],
+              Some(self.centroiding),
+              |c| { to_msg(SettingsMsg::Centroiding(c)) }
+            ).width(Length::FillPortion(1))
+          )
+      );
+
+    let (status, save) = match self.parse_inputs() {
+      Err(why) => (
+        text(why).size(12u16).color(Color { r: 0.8, g: 0.0, b: 0.0, a: 1.0 }),
+        button(text("Save"))
+      ),
+      Ok(settings) => (
+        text("").size(12u16),
+        button(text("Save")).on_press(Message::SetSettings(settings))
+      ),
+    };
+
+    column().padding(20).spacing(20)
+      .push(text("Settings").size(20u16))
+      .push(form)
+      .push(status)
+      .push(
+        row().spacing(10)
+          .push(save)
+          .push(
+            button(text("Ribbon Buttons..."))
+              .on_press(Message::Popup(popups::new_customize_ribbon(self.ribbon.clone())))
+          )
+      ).into()
+  }
+
+  fn field<'a>(
+    &self,
+    label: &str,
+    value: &String,
+    msg:   fn(String) -> SettingsMsg
+  ) -> Element<'a, Message> {
+
+    row()
+      .push(text(label).width(Length::FillPortion(1)))
+      .push(
+        text_input("", value, move |s| { to_msg(msg(s)) })
+          .width(Length::FillPortion(1))
+      ).into()
+  }
+
+  pub fn update(&mut self, msg: SettingsMsg) {
+    match msg {
+      SettingsMsg::Color(i, s) => {
+        if i < self.colors.len() {
+          self.colors[i] = s;
+        }
+      }
+      SettingsMsg::SNRatio(s)        => { self.sn_ratio        = s; }
+      SettingsMsg::AbsIntensity(s)   => { self.abs_intensity   = s; }
+      SettingsMsg::RelIntensity(s)   => { self.rel_intensity   = s; }
+      SettingsMsg::ImportDir(s)      => { self.import_dir      = s; }
+      SettingsMsg::LabelPrecision(s) => { self.label_precision = s; }
+      SettingsMsg::MassTolerance(s)  => { self.mass_tolerance  = s; }
+      SettingsMsg::NoiseWindow(s)    => { self.noise_window    = s; }
+      SettingsMsg::ShowNoise(b)      => { self.show_noise      = b; }
+      SettingsMsg::Centroiding(c)    => { self.centroiding     = c; }
+    }
+  }
+
+  // Settings can only be saved once every field makes sense
+  fn parse_inputs(&self) -> Result<Settings, String> {
+
+    if let Some(c) = self.colors.iter().find(|c| { parse_color(c).is_none() }) {
+      return Err(format!("\"{}\" is not a colour like #2b4d74", c));
+    }
+
+    let number = |name: &str, s: &String| -> Result<f64, String> {
+      match f64::from_str(s.trim()) {
+        Ok(v) if v >= 0.0 => Ok(v),
+        _ => Err(format!("{} must be a positive number", name)),
+      }
+    };
+
+    let label_precision = match usize::from_str(self.label_precision.trim()) {
+      Ok(p) if p <= 10 => p,
+      _ => { return Err("Label decimals must be a whole number up to 10".to_string()); }
+    };
+
+    Ok(Settings {
+      colors:          self.colors.iter().map(|c| { c.trim().to_string() }).collect(),
+      sn_ratio:        number("S/N-ratio", &self.sn_ratio)?,
+      abs_intensity:   number("Abs. intensity", &self.abs_intensity)?,
+      rel_intensity:   number("Rel. intensity", &self.rel_intensity)?,
+      import_dir:      self.import_dir.trim().to_string(),
+      label_precision: label_precision,
+      mass_tolerance:  number("Mass tolerance", &self.mass_tolerance)?,
+      noise_window:    number("Noise window", &self.noise_window)?,
+      show_noise:      self.show_noise,
+      centroiding:     self.centroiding,
+    })
+  }
+
+}
+
+fn to_msg(msg: SettingsMsg) -> Message {
+  Message::ForPopup(ForPopup::ForSettings(msg))
+}
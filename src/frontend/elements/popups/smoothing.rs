@@ -0,0 +1,134 @@
+use crate::Message;
+use crate::backend::smoothing::{ Method, Smoothing, Unit };
+use crate::frontend::elements::popups::ForPopup;
+
+use std::str::FromStr;
+
+use iced::{
+  Length,
+
+  pure::{
+    Element, column, text, row, text_input, button, pick_list,
+  }
+};
+
+#[derive(Debug, Clone)]
+pub enum SmoothingMsg {
+  Method(Method),
+  Width(String),
+  Unit(Unit),
+  Order(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct State {
+  method: Method,
+  width:  String,
+  unit:   Unit,
+  order:  String,
+}
+
+impl Default for State {
+  fn default() -> Self {
+    let smoothing = Smoothing::default();
+    State {
+      method: smoothing.method,
+      width:  smoothing.width.to_string(),
+      unit:   smoothing.unit,
+      order:  smoothing.order.to_string(),
+    }
+  }
+}
+
+impl State {
+
+  pub fn view<'a>(&self) -> Element<'a, Message> {
+
+    let mut form = column().spacing(10)
+      .push(
+        row()
+          .push(text("Method").width(Length::FillPortion(1)))
+          .push(
+            pick_list(
+              &Method::ALL[..],
+              Some(self.method),
+              |m| { Message::ForPopup(ForPopup::ForSmoothing(SmoothingMsg::Method(m))) }
+            ).width(Length::FillPortion(1))
+          )
+      )
+      .push(
+        row().spacing(5)
+          .push(text("Window").width(Length::FillPortion(2)))
+          .push(
+            text_input(
+              "",
+              &self.width,
+              |s| { Message::ForPopup(ForPopup::ForSmoothing(SmoothingMsg::Width(s))) }
+            ).width(Length::FillPortion(1))
+          )
+          .push(
+            pick_list(
+              &Unit::ALL[..],
+              Some(self.unit),
+              |u| { Message::ForPopup(ForPopup::ForSmoothing(SmoothingMsg::Unit(u))) }
+            ).width(Length::FillPortion(1))
+          )
+      );
+
+    if self.method == Method::SavitzkyGolay {
+      form = form.push(
+        row()
+          .push(text("Polynomial Order").width(Length::FillPortion(1)))
+          .push(
+            text_input(
+              "",
+              &self.order,
+              |s| { Message::ForPopup(ForPopup::ForSmoothing(SmoothingMsg::Order(s))) }
+            ).width(Length::FillPortion(1))
+          )
+      );
+    }
+
+    let go = match self.parse_inputs() {
+      Some(s) => button(text("Smooth")).on_press(Message::Smooth(s)),
+      None    => button(text("Smooth")),
+    };
+
+    column().padding(20).spacing(20)
+      .push(text("Smoothing").size(20u16))
+      .push(form)
+      .push(go)
+      .into()
+  }
+
+  pub fn update(&mut self, msg: SmoothingMsg) {
+    match msg {
+      SmoothingMsg::Method(m) => {
+        self.method = m;
+      }
+      SmoothingMsg::Width(s) => {
+        self.width = s;
+      }
+      SmoothingMsg::Unit(u) => {
+        self.unit = u;
+      }
+      SmoothingMsg::Order(s) => {
+        self.order = s;
+      }
+    }
+  }
+
+  // None while an input is not a usable number
+  fn parse_inputs(&self) -> Option<Smoothing> {
+    let width = f64::from_str(self.width.trim()).ok().filter(|w| { *w > 0.0 })?;
+    let order = usize::from_str(self.order.trim()).ok().filter(|o| { *o <= 6 })?;
+
+    Some(Smoothing {
+      method: self.method,
+      width,
+      unit:   self.unit,
+      order,
+    })
+  }
+
+}
@@ -16,14 +16,11 @@ use iced::{
   Alignment
 };
 
-use crate::Message;
-use crate::backend::{ Data, MSPoint };
+use std::cell::Cell;
 
-const COLORS: [Color; 3] = [
-  Color { r: 0.169, g: 0.302, b: 0.455, a: 1.0 }, // blue
-  Color { r: 0.824, g: 0.329, b: 0.4  , a: 1.0 }, // red
-  Color { r: 0.651, g: 0.255, b: 0.523, a: 1.0 }, // purple
-];
+use crate::Message;
+use crate::backend::{ Data, MSPoint, calcs, project::View };
+use crate::config::Settings;
 
 const RULER_GIRTH: f32 = 10.0;
 
@@ -38,6 +35,8 @@ pub struct State {
   pub r_click: Option<Point>,
   pub selection: (f32, f32), // only valid if rClick is not None
   pub modifiers: keyboard::Modifiers,
+  pub baseline: Option<(usize, Vec<f64>)>, // previewed under the dataset with this index
+  width: Cell<f32>, // of the plot area when it was last drawn
 }
 
 impl Default for State {
@@ -53,6 +52,8 @@ impl Default for State {
       selection: (0.0, 0.0),
       cache: canvas::Cache::default(),
       modifiers: keyboard::Modifiers::default(),
+      baseline: None,
+      width: Cell::new(0.0),
     }
   }
 }
@@ -71,7 +72,8 @@ pub enum PlotMsg {
 impl State {
   pub fn view<'a>(
     &'a self,
-    data: &'a Data
+    data:     &'a Data,
+    settings: &'a Settings,
   ) -> Element<'a, Message> {
 
     column().padding(20).spacing(10).align_items(Alignment::Center)
@@ -79,6 +81,7 @@ impl State {
         Canvas::new(Plot {
           state: self,
           data,
+          settings,
         })
         .height(Length::Fill)
         .width(Length::Fill)
@@ -173,6 +176,44 @@ impl State {
     }
   }
   
+  // Shows the whole m/z range of all datasets at full height
+  pub fn zoom_to_fit(&mut self, data: &Data) {
+    let range = (data.mz_max - data.mz_min) as f32;
+    let width = self.width.get() - RULER_GIRTH;
+    
+    if data.sets.is_empty() || range <= 0.0 || width <= 0.0 {
+      return;
+    }
+    
+    self.sx = width / (range * 1.02);
+    self.sy = 0.9;
+    self.x0 = self.sx * (data.mz_min as f32 - range * 0.01) - RULER_GIRTH;
+    self.y0 = 0.0;
+    self.req_redraw();
+  }
+  
+  pub fn show_baseline(&mut self, preview: Option<(usize, Vec<f64>)>) {
+    self.baseline = preview;
+    self.req_redraw();
+  }
+  
+  pub fn view_state(&self) -> View {
+    View {
+      x0: self.x0,
+      y0: self.y0,
+      sx: self.sx,
+      sy: self.sy,
+    }
+  }
+  
+  pub fn restore_view(&mut self, view: View) {
+    self.x0 = view.x0;
+    self.y0 = view.y0;
+    self.sx = view.sx;
+    self.sy = view.sy;
+    self.req_redraw();
+  }
+  
   pub fn req_redraw(&mut self) {
     self.cache.clear()
   }
@@ -180,7 +221,8 @@ impl State {
 
 pub struct Plot<'a> {
   state: &'a State,
-  data: &'a Data
+  data: &'a Data,
+  settings: &'a Settings,
 }
 
 pub enum Interaction {
@@ -326,6 +368,7 @@ impl<'a> canvas::Program<Message> for Plot<'a> {
       width:  big_bounds.width  - 40.0,
       height: big_bounds.height - 40.0
     };
+    self.state.width.set(bounds.width);
     
     vec![
     
@@ -379,7 +422,7 @@ impl<'a> canvas::Program<Message> for Plot<'a> {
 //        frame.translate(Vector { x: -self.state.x0, y: 0.0 });
         
         let mut i = 0;
-        for ds in self.data.sets.iter().filter(|ds| {ds.visible}) {
+        for (index, ds) in self.data.sets.iter().enumerate().filter(|(_, ds)| {ds.visible}) {
           let points = &ds.points;
           let peaks  = &ds.peaks;
           
@@ -403,15 +446,62 @@ impl<'a> canvas::Program<Message> for Plot<'a> {
               j += 1;
             }
           });
-          frame.stroke(&curve, Stroke::default().with_width(1.0).with_color(COLORS[i%3]));
+          frame.stroke(&curve, Stroke::default().with_width(1.0).with_color(self.dataset_color(i)));
+          
+          // baseline preview
+          if let Some((b_index, baseline)) = &self.state.baseline {
+            if *b_index == index && baseline.len() == points.len() {
+              let from = points.partition_point(|pt| { pt.mz < lower }).min(points.len() - 1);
+              let to   = points.partition_point(|pt| { pt.mz < upper });
+              
+              let path = Path::new(|p| {
+                p.move_to(self.to_coords(&MSPoint { mz: points[from].mz, int: baseline[from], snr: 0.0 }, &bounds));
+                for j in from+1..to {
+                  p.line_to(self.to_coords(&MSPoint { mz: points[j].mz, int: baseline[j], snr: 0.0 }, &bounds));
+                }
+              });
+              frame.stroke(
+                &path,
+                Stroke::default()
+                  .with_width(1.5)
+                  .with_color(Color {r: 0.9, g: 0.6, b: 0.0, a: 1.0}));
+            }
+          }
+          
+          // noise band of the selected dataset
+          if self.settings.show_noise && index == self.data.curr_ds {
+            let mut mzs = vec![lower.max(ds.mz_min)];
+            mzs.extend(ds.noise.iter().map(|n| { n.mz }).filter(|mz| { *mz > lower && *mz < upper }));
+            mzs.push(upper.min(ds.mz_max));
+            
+            let band: Vec<(f64, f64, f64)> = mzs.iter()
+              .map(|mz| {
+                let (level, width) = calcs::noise_at(&ds.noise, *mz);
+                (*mz, level + width / 2.0, level - width / 2.0)
+              })
+              .collect();
+            
+            let path = Path::new(|p| {
+              p.move_to(self.to_coords(&MSPoint { mz: band[0].0, int: band[0].1, snr: 0.0 }, &bounds));
+              for (mz, top, _) in band.iter().skip(1) {
+                p.line_to(self.to_coords(&MSPoint { mz: *mz, int: *top, snr: 0.0 }, &bounds));
+              }
+              for (mz, _, bottom) in band.iter().rev() {
+                p.line_to(self.to_coords(&MSPoint { mz: *mz, int: *bottom, snr: 0.0 }, &bounds));
+              }
+              p.close();
+            });
+            frame.fill(&path, Color { a: 0.25, ..self.dataset_color(i) });
+          }
           i += 1;
           
           // draw peaks
-          for k in peaks {
-//          for k in &ds.maxima {
-            let MSPoint {int: ypk, ..} = points[*k];
-            
-            let Point {x: x_, y: y_} = self.to_coords(&points[*k], &bounds);
+          for pk in peaks {
+            // marked at the fitted apex rather than the highest point
+            let Point {x: x_, y: y_} = self.to_coords(
+              &MSPoint { mz: pk.mz, int: pk.int, snr: 0.0 }, 
+              &bounds
+            );
             
             let path = Path::new(|p| {
               p.move_to(Point {x: x_, y: bounds.height - RULER_GIRTH - self.state.y0});
@@ -423,12 +513,25 @@ impl<'a> canvas::Program<Message> for Plot<'a> {
                 .with_width(1.0)
                 .with_color(Color {r: 1.0, g: 0.0, b: 0.0, a: 1.0}));
             
+            let content = if pk.fwhm > 0.0 {
+              format!("{:.*} (R {:.0})", self.settings.label_precision, pk.mz, pk.resolution())
+            } else {
+              format!("{:.*}", self.settings.label_precision, pk.mz)
+            };
             let txt = Text {
-              content: format!("{:.1}", ypk),
+              content: content,
               position: Point {x: x_, y: y_ - 10.0},
               ..Text::default()
             };
             frame.fill_text(txt);
+            
+            if let Some(annotation) = ds.annotation_within(pk.index, self.settings.mass_tolerance) {
+              frame.fill_text(Text {
+                content: annotation.label.clone(),
+                position: Point {x: x_, y: y_ - 22.0},
+                ..Text::default()
+              });
+            }
           }
         }
         
@@ -438,6 +541,11 @@ impl<'a> canvas::Program<Message> for Plot<'a> {
 }
 
 impl Plot<'_> {
+  fn dataset_color(&self, i: usize) -> Color {
+    let (r, g, b) = self.settings.color(i);
+    Color { r, g, b, a: 1.0 }
+  }
+  
   fn max_pt_in_highlight(&self, bounds: &Rectangle) -> Option<usize> {
     
     if self.data.sets.len() > self.data.curr_ds {
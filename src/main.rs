@@ -1,10 +1,20 @@
-mod backend;
+mod cli;
+mod config;
 mod frontend;
 
+use moremass_core as backend;
+
 use crate::backend::{
-  parser::parse_mzxml_badly
+  Dataset, Peak, Run,
+  baseline::Baseline,
+  smoothing::Smoothing,
+  cwt::Cwt,
+  parser::{ ParseError, Progress, parse_file, load_scans },
+  project::{ self, Project, save_project, load_project },
+  writer::{ self, write_file },
 };
 
+use crate::config::{ Config, RibbonAction, Settings };
 use crate::frontend::{
   plot, elements::{
     popups::{ self, WhichPopup },
@@ -12,27 +22,48 @@ use crate::frontend::{
   }
 };
 use iced::{
-  Settings,
+  Command, Subscription,
+  executor, time,
   pure::{
-    Element, Sandbox, 
+    Element, Application, 
   }
 };
 
+use std::sync::Arc;
+use std::time::Duration;
+
 
 pub fn main() -> iced::Result {
-  MoreMass::run(Settings {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  
+  // batch processing runs without opening a window
+  if args.first().map(|a| { a == "--batch" }).unwrap_or(false) {
+    std::process::exit(cli::run(&args[1..]));
+  }
+  
+  MoreMass::run(iced::Settings {
     antialiasing: true,
-    ..Settings::default()
+    ..iced::Settings::default()
   })
 }
 
 // Application State ------------------
 #[derive(Default)]
 pub struct MoreMass {
-  data:  backend::Data,
+  data:   backend::Data,
+  config: Config,
   
   plot:  plot::State,
   popup: WhichPopup,
+  
+  loading: Option<Loading>,
+  queue:   Vec<String>, // files waiting to be loaded
+}
+
+// Files are read in the background, one at a time
+pub struct Loading {
+  pub what:     String,
+  pub progress: Progress,
 }
 
 // Message Types ----------------------
@@ -51,26 +82,52 @@ pub enum Message {
   SelectDataset( usize ),
   AddPeak( usize ),
   RemovePeaks( f64, f64 ),
-  FindPeaks( f64, f64, f64 ),
+  FindPeaks( f64, f64, f64, Option<Cwt> ), // by wavelet transform if given
   LoadFromPath( String ),
+  LoadFromPaths( Vec<String> ),
+  Loaded( String, Result<Run, Arc<ParseError>> ),
+  ProjectLoaded( String, Result<Project, Arc<ParseError>> ),
+  LoadScans( u32, u32 ),
+  LoadAllScans,
+  ScansLoaded( Result<Vec<Dataset>, Arc<ParseError>> ),
+  CancelLoad,
+  PeaksFound( usize, u64, Vec<Peak> ), // index and generation of the dataset
+  PreviewBaseline( Baseline ),
+  BaselineEstimated( usize, u64, Vec<f64> ),
+  SubtractBaseline( Baseline ),
+  Smooth( Smoothing ),
+  Processed( usize, u64, Dataset ),
+  Export( writer::Format, writer::Encoding, String ),
+  SaveProject( String ),
+  SetRibbon( Vec<RibbonAction> ),
+  SetSettings( Settings ),
+  ClearRecent,
+  ZoomToFit,
+  ToggleNoise,
   Clear,
   
   Noop
 }
 
 
-impl Sandbox for MoreMass {
-  type Message = Message;
+impl Application for MoreMass {
+  type Executor = executor::Default;
+  type Message  = Message;
+  type Flags    = ();
   
-  fn new() -> Self {
-    MoreMass::default()
+  fn new(_flags: ()) -> (Self, Command<Message>) {
+    let mm = MoreMass {
+      config: Config::load(),
+      ..MoreMass::default()
+    };
+    (mm, Command::none())
   }
 
   fn title(&self) -> String {
     String::from("MoreMass")
   }
   
-  fn update(&mut self, message: Message) {
+  fn update(&mut self, message: Message) -> Command<Message> {
     match message {
     
       Message::Popup(which) => {
@@ -78,13 +135,191 @@ impl Sandbox for MoreMass {
       }
       Message::ClosePopup => {
         self.popup = WhichPopup::NoPopup;
+        self.plot.show_baseline(None);
+        return self.load_next();
       }
       
       Message::LoadFromPath(s) => {
-        self.data.push(parse_mzxml_badly(&s));
         self.popup = WhichPopup::NoPopup;
-        self.plot.rethink_bounds(&self.data);
+        
+        if self.loading.is_some() {
+          self.queue.push(s);
+        } else if project::is_project(&s) {
+          let progress = self.start_loading(s.clone());
+          return Command::perform(
+            read_project(s.clone(), progress),
+            move |result| { Message::ProjectLoaded(s.clone(), result) }
+          );
+        } else {
+          let progress = self.start_loading(s.clone());
+          return Command::perform(
+            read_file(s.clone(), progress),
+            move |result| { Message::Loaded(s.clone(), result) }
+          );
+        }
+      }
+      
+      Message::Loaded(s, result) => {
+        self.loading = None;
+        
+        match result {
+          Err(why) if matches!(*why, ParseError::Cancelled) => {}
+          Err(why) => {
+            self.popup = popups::new_error(format!("Could not load {}:\n{}", s, why));
+          }
+          Ok(run) if run.scans.len() > 1 => {
+            // let the user pick which scans to use
+            self.remember(&s);
+            self.popup = popups::new_select_scan(run);
+          }
+          Ok(run) => {
+            self.remember(&s);
+            let num = run.scans[0].num;
+            self.push_dataset(run.to_dataset(num, num));
+            self.plot.rethink_bounds(&self.data);
+            self.plot.req_redraw();
+          }
+        }
+        return self.load_next();
+      }
+      
+      // datasets of the project get new generations, so work still running
+      // on the ones it replaces is dropped when it finishes
+      Message::ProjectLoaded(s, result) => {
+        self.loading = None;
+        
+        match result {
+          Err(why) if matches!(*why, ParseError::Cancelled) => {}
+          Err(why) => {
+            self.popup = popups::new_error(format!("Could not open project {}:\n{}", s, why));
+          }
+          Ok(p) => {
+            self.data = p.data;
+            self.plot.restore_view(p.view);
+            self.remember(&s);
+          }
+        }
+        return self.load_next();
+      }
+      
+      Message::LoadFromPaths(paths) => {
+        self.popup = WhichPopup::NoPopup;
+        self.queue.extend(paths);
+        return self.load_next();
+      }
+      
+      // scans of indexed files are only read once they are picked
+      Message::LoadScans(from, to) => {
+        if self.loading.is_none() {
+          if let WhichPopup::SelectScan(state) = std::mem::take(&mut self.popup) {
+            let progress = self.start_loading(format!("{} #{}-{}", state.run.metadata.title, from, to));
+            return Command::perform(
+              read_scans(state.run, vec![(from, to)], progress),
+              Message::ScansLoaded
+            );
+          }
+        }
+      }
+      
+      Message::LoadAllScans => {
+        if self.loading.is_none() {
+          if let WhichPopup::SelectScan(state) = std::mem::take(&mut self.popup) {
+            let progress = self.start_loading(state.run.metadata.title.clone());
+            let each = state.run.scans.iter().map(|scan| { (scan.num, scan.num) }).collect();
+            return Command::perform(
+              read_scans(state.run, each, progress),
+              Message::ScansLoaded
+            );
+          }
+        }
+      }
+      
+      Message::ScansLoaded(result) => {
+        self.loading = None;
+        
+        match result {
+          Err(why) if matches!(*why, ParseError::Cancelled) => {}
+          Err(why) => {
+            self.popup = popups::new_error(format!("Could not read scans:\n{}", why));
+          }
+          Ok(sets) => {
+            for ds in sets {
+              self.push_dataset(Some(ds));
+            }
+            self.plot.rethink_bounds(&self.data);
+            self.plot.req_redraw();
+          }
+        }
+        return self.load_next();
+      }
+      
+      // the load ends with an error once the parser notices. Files that
+      // were still waiting are dropped as well.
+      Message::CancelLoad => {
+        if let Some(loading) = &self.loading {
+          loading.progress.cancel();
+        }
+        self.queue.clear();
+      }
+      
+      Message::Export(format, encoding, path) => {
+        if self.data.curr_ds < self.data.sets.len() {
+          let ds = &self.data.sets[self.data.curr_ds];
+          self.popup = match write_file(ds, &path, format, encoding) {
+            Err(why) => popups::new_error(format!("Could not write {}:\n{}", path, why)),
+            Ok(_)    => WhichPopup::NoPopup,
+          };
+        }
+      }
+      
+      Message::SaveProject(path) => {
+        self.popup = match save_project(&self.data, self.plot.view_state(), &path) {
+          Err(why) => popups::new_error(format!("Could not save {}:\n{}", path, why)),
+          Ok(_)    => WhichPopup::NoPopup,
+        };
+      }
+      
+      Message::SetRibbon(actions) => {
+        self.config.ribbon = actions;
+        self.popup = match self.config.save() {
+          Err(why) => popups::new_error(format!("Could not save the settings:\n{}", why)),
+          Ok(_)    => WhichPopup::NoPopup,
+        };
+      }
+      
+      Message::SetSettings(settings) => {
+        if settings.noise_window != self.config.settings.noise_window {
+          for ds in self.data.sets.iter_mut() {
+            ds.set_noise_window(settings.noise_window);
+          }
+        }
+        if settings.centroiding != self.config.settings.centroiding {
+          for ds in self.data.sets.iter_mut() {
+            ds.set_centroiding(settings.centroiding);
+          }
+        }
+        self.config.settings = settings;
+        self.plot.req_redraw();
+        self.popup = match self.config.save() {
+          Err(why) => popups::new_error(format!("Could not save the settings:\n{}", why)),
+          Ok(_)    => WhichPopup::NoPopup,
+        };
+      }
+      
+      Message::ClearRecent => {
+        self.config.recent.clear();
+        self.popup = WhichPopup::NoPopup;
+        let _ = self.config.save();
+      }
+      
+      Message::ZoomToFit => {
+        self.plot.zoom_to_fit(&self.data);
+      }
+      
+      Message::ToggleNoise => {
+        self.config.settings.show_noise = !self.config.settings.show_noise;
         self.plot.req_redraw();
+        let _ = self.config.save();
       }
       
       Message::ToggleVisibility(index) => {
@@ -109,7 +344,23 @@ impl Sandbox for MoreMass {
 //            self.data.push(Some(Dataset::default()));
           }
           WhichFileOp::Open => {
-            self.popup = popups::new_find_file();
+            self.popup = popups::new_find_file(&self.config.settings);
+          }
+          WhichFileOp::OpenRecent => {
+            self.popup = popups::new_recent_files(self.config.recent.clone());
+          }
+          WhichFileOp::SaveProject => {
+            self.popup = popups::new_save_project();
+          }
+          WhichFileOp::Export => {
+            if self.data.curr_ds < self.data.sets.len() {
+              self.popup = popups::new_export(writer::Format::MgfPeaks);
+            }
+          }
+          WhichFileOp::ExportPeaks => {
+            if self.data.curr_ds < self.data.sets.len() {
+              self.popup = popups::new_export(writer::Format::PeaksCsv);
+            }
           }
           WhichFileOp::CloseAll => {
             self.data = crate::backend::Data::default();
@@ -124,7 +375,17 @@ impl Sandbox for MoreMass {
         match which {
           WhichProcessingOp::FindPeaks => {
             if self.data.curr_ds < self.data.sets.len() {
-              self.popup = popups::new_find_peaks();
+              self.popup = popups::new_find_peaks(&self.config.settings);
+            }
+          }
+          WhichProcessingOp::Smoothing => {
+            if self.data.curr_ds < self.data.sets.len() {
+              self.popup = popups::new_smoothing();
+            }
+          }
+          WhichProcessingOp::BaselineCorrection => {
+            if self.data.curr_ds < self.data.sets.len() {
+              self.popup = popups::new_baseline();
             }
           }
         }
@@ -152,12 +413,96 @@ impl Sandbox for MoreMass {
         self.plot.req_redraw();
       }
       
-      Message::FindPeaks(ratio, abs_int, rel_int) => {
+      Message::FindPeaks(ratio, abs_int, rel_int, cwt) => {
+        self.popup = WhichPopup::NoPopup;
+        
         if self.data.sets.len() > self.data.curr_ds {
-          self.data.sets[self.data.curr_ds].find_peaks(ratio, abs_int, rel_int, true);
+          let index      = self.data.curr_ds;
+          let mut ds     = self.data.sets[index].clone();
+          let generation = ds.generation();
+          return Command::perform(
+            in_background(move || {
+              match cwt {
+                Some(cwt) => ds.find_peaks_cwt(&cwt, ratio, abs_int, rel_int, true),
+                None      => ds.find_peaks(ratio, abs_int, rel_int, true),
+              }
+              ds.peaks
+            }),
+            move |peaks| { Message::PeaksFound(index, generation, peaks) }
+          );
+        }
+      }
+      
+      // results for datasets that were edited, closed or replaced meanwhile
+      // are dropped
+      Message::PeaksFound(index, generation, peaks) => {
+        if self.is_current(index, generation) {
+          self.data.sets[index].peaks = peaks;
+          self.data.sets[index].touch();
           self.plot.req_redraw();
         }
+      }
+      
+      Message::PreviewBaseline(baseline) => {
+        if self.data.sets.len() > self.data.curr_ds {
+          let index      = self.data.curr_ds;
+          let points     = self.data.sets[index].points.clone();
+          let generation = self.data.sets[index].generation();
+          return Command::perform(
+            in_background(move || { baseline.estimate(&points) }),
+            move |estimate| { Message::BaselineEstimated(index, generation, estimate) }
+          );
+        }
+      }
+      
+      // the preview is dropped if the popup was closed in the meantime
+      Message::BaselineEstimated(index, generation, estimate) => {
+        if matches!(self.popup, WhichPopup::Baseline(_)) && self.is_current(index, generation) {
+          self.plot.show_baseline(Some((index, estimate)));
+        }
+      }
+      
+      Message::SubtractBaseline(baseline) => {
+        self.popup = WhichPopup::NoPopup;
+        self.plot.show_baseline(None);
+        
+        if self.data.sets.len() > self.data.curr_ds {
+          let index      = self.data.curr_ds;
+          let mut ds     = self.data.sets[index].clone();
+          let generation = ds.generation();
+          return Command::perform(
+            in_background(move || {
+              ds.subtract_baseline(&baseline);
+              ds
+            }),
+            move |ds| { Message::Processed(index, generation, ds) }
+          );
+        }
+      }
+      
+      Message::Smooth(smoothing) => {
         self.popup = WhichPopup::NoPopup;
+        
+        if self.data.sets.len() > self.data.curr_ds {
+          let index      = self.data.curr_ds;
+          let mut ds     = self.data.sets[index].clone();
+          let generation = ds.generation();
+          return Command::perform(
+            in_background(move || {
+              ds.smooth(&smoothing);
+              ds
+            }),
+            move |ds| { Message::Processed(index, generation, ds) }
+          );
+        }
+      }
+      
+      Message::Processed(index, generation, ds) => {
+        if self.is_current(index, generation) {
+          self.data.sets[index] = ds;
+          self.data.update_ranges();
+          self.plot.req_redraw();
+        }
       }
       
       Message::Clear => {
@@ -167,6 +512,17 @@ impl Sandbox for MoreMass {
       }
       Message::Noop => { }
     }
+    
+    Command::none()
+  }
+  
+  // redraws the progress bar while something is loading
+  fn subscription(&self) -> Subscription<Message> {
+    if self.loading.is_some() {
+      time::every(Duration::from_millis(100)).map(|_| { Message::Noop })
+    } else {
+      Subscription::none()
+    }
   }
   
   fn view(&self) -> Element<Message> {
@@ -176,3 +532,114 @@ impl Sandbox for MoreMass {
   }
 }
 
+impl MoreMass {
+  // Starts on the next file in the queue, unless a file is still loading or
+  // a popup (e.g. scan selection for the previous file) waits for the user
+  fn load_next(&mut self) -> Command<Message> {
+    let idle = self.loading.is_none() && matches!(self.popup, WhichPopup::NoPopup);
+    
+    if idle && !self.queue.is_empty() {
+      let s = self.queue.remove(0);
+      self.update(Message::LoadFromPath(s))
+    } else {
+      Command::none()
+    }
+  }
+  
+  // Adds a file to the recent files. Failing to write the config is not worth
+  // interrupting the user for; the list is simply not kept then.
+  fn remember(&mut self, s: &String) {
+    self.config.add_recent(s);
+    let _ = self.config.save();
+  }
+  
+  // new datasets get their noise estimated and peaks fitted as set up in
+  // the settings
+  fn push_dataset(&mut self, ds: Option<Dataset>) {
+    let window      = self.config.settings.noise_window;
+    let centroiding = self.config.settings.centroiding;
+    
+    self.data.push(ds.map(|mut ds| {
+      if window > 0.0 {
+        ds.set_noise_window(window);
+      }
+      ds.set_centroiding(centroiding);
+      ds
+    }));
+  }
+  
+  // whether the dataset at index is still the one work was started on
+  fn is_current(&self, index: usize, generation: u64) -> bool {
+    self.data.sets.get(index)
+      .map(|ds| { ds.generation() == generation })
+      .unwrap_or(false)
+  }
+  
+  fn start_loading(&mut self, what: String) -> Progress {
+    let progress = Progress::default();
+    self.loading = Some(Loading {
+      what:     what,
+      progress: progress.clone(),
+    });
+    progress
+  }
+}
+
+// Reads a file on a worker thread. Files with a single scan have it read
+// right away, the others wait for the user to pick scans.
+async fn read_file( s: String, progress: Progress ) -> Result<Run, Arc<ParseError>> {
+
+  in_background(move || -> Result<Run, Arc<ParseError>> {
+    let mut run = parse_file(&s, &progress)?;
+    
+    if run.scans.len() == 1 {
+      let num = run.scans[0].num;
+      load_scans(&mut run, num, num, &progress)?;
+    }
+    
+    Ok(run)
+  }).await
+}
+
+// Reads a project on a worker thread
+async fn read_project( s: String, progress: Progress ) -> Result<Project, Arc<ParseError>> {
+
+  in_background(move || -> Result<Project, Arc<ParseError>> {
+    Ok(load_project(&s, &progress)?)
+  }).await
+}
+
+// Reads the points of the given ranges of scans and sums up each range
+async fn read_scans(
+  mut run:  Run, 
+  ranges:   Vec<(u32, u32)>, 
+  progress: Progress
+) -> Result<Vec<Dataset>, Arc<ParseError>> {
+
+  in_background(move || -> Result<Vec<Dataset>, Arc<ParseError>> {
+    let from = ranges.iter().map(|(from, _)| { *from }).min().unwrap_or(0);
+    let to   = ranges.iter().map(|(_, to)| { *to }).max().unwrap_or(0);
+    
+    load_scans(&mut run, from, to, &progress)?;
+    
+    Ok(
+      ranges.iter()
+        .filter_map(|(from, to)| { run.to_dataset(*from, *to) })
+        .collect()
+    )
+  }).await
+}
+
+// Runs parsing and processing on tokio's blocking threads, so that they do
+// not hold up the executor. Panics are passed on.
+async fn in_background<T, F>( work: F ) -> T 
+where
+  T: Send + 'static,
+  F: FnOnce() -> T + Send + 'static,
+{
+  match tokio::task::spawn_blocking(work).await {
+    Err(why)   => std::panic::resume_unwind(why.into_panic()),
+    Ok(result) => result,
+  }
+}
+